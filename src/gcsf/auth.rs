@@ -3,13 +3,19 @@
 //! This module provides authentication for users running GCSF on remote servers
 //! where the browser is on a different machine. It supports both automatic localhost
 //! redirect (when browser and GCSF are on the same machine) and manual URL paste
-//! (when they're on different machines).
+//! (when they're on different machines), as well as unattended service-account
+//! authentication for servers and CI with no human available to click through any
+//! of the above.
 
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use failure::{err_msg, Error};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpListener;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
@@ -17,13 +23,13 @@ use time::OffsetDateTime;
 use url::Url;
 
 /// Token structure compatible with yup_oauth2's storage format.
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct StoredTokenEntry {
     scopes: Vec<String>,
     token: StoredToken,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct StoredToken {
     access_token: String,
     refresh_token: String,
@@ -32,26 +38,44 @@ struct StoredToken {
     id_token: Option<String>,
 }
 
+impl StoredToken {
+    /// Reconstructs the `OffsetDateTime` encoded by `expires_at`, the inverse of how
+    /// `save_tokens` lays it out.
+    fn expires_at(&self) -> Result<OffsetDateTime, Error> {
+        let (year, ordinal, hour, minute, second, nanosecond, ..) = self.expires_at;
+        let date = time::Date::from_ordinal_date(year, ordinal)
+            .map_err(|e| err_msg(format!("Invalid stored expires_at date: {}", e)))?;
+        let time = time::Time::from_hms_nano(hour, minute, second, nanosecond)
+            .map_err(|e| err_msg(format!("Invalid stored expires_at time: {}", e)))?;
+
+        Ok(date.with_time(time).assume_utc())
+    }
+}
+
 #[derive(Deserialize)]
 struct TokenResponse {
     access_token: String,
     refresh_token: Option<String>,
     expires_in: Option<u64>,
-    #[allow(dead_code)]
     token_type: String,
 }
 
 /// Performs headless OAuth login.
 ///
-/// Starts a server on the specified port and also accepts pasted redirect URLs.
+/// Binds the redirect listener to the first of `candidate_ports` that's actually free (so a
+/// stale process or a reserved port doesn't break login), auto-opens the authorization URL in
+/// the default browser when a display is available, and also accepts pasted redirect URLs.
 /// Returns the authorization code from whichever method succeeds first.
 pub fn headless_login(
     client_id: &str,
     client_secret: &str,
-    token_file: &Path,
-    port: u16,
+    token_store: &TokenStore,
+    candidate_ports: &[u16],
 ) -> Result<(), Error> {
+    let (listener, port) = bind_redirect_listener(candidate_ports)?;
     let redirect_uri = format!("http://127.0.0.1:{}", port);
+    let (code_verifier, code_challenge) = generate_pkce_pair();
+    let state = generate_state_token();
 
     // Build the authorization URL
     let auth_url = format!(
@@ -61,13 +85,23 @@ pub fn headless_login(
          response_type=code&\
          scope=https://www.googleapis.com/auth/drive&\
          access_type=offline&\
-         prompt=consent",
+         prompt=consent&\
+         code_challenge={}&\
+         code_challenge_method=S256&\
+         state={}",
         urlencoding::encode(client_id),
-        urlencoding::encode(&redirect_uri)
+        urlencoding::encode(&redirect_uri),
+        urlencoding::encode(&code_challenge),
+        urlencoding::encode(&state)
     );
 
     println!("\n=== GCSF Authentication ===\n");
-    println!("Please visit this URL to authorize GCSF:\n");
+    if webbrowser::open(&auth_url).is_ok() {
+        println!("Opened the authorization page in your browser.");
+        println!("If it didn't open, visit this URL manually:\n");
+    } else {
+        println!("Please visit this URL to authorize GCSF:\n");
+    }
     println!("{}\n", auth_url);
     println!("After authorizing:");
     println!("  - If running locally: authentication completes automatically");
@@ -75,42 +109,181 @@ pub fn headless_login(
     println!("    (it will show 'connection refused') and paste it below\n");
 
     // Get code via redirect server or manual paste
-    let code = get_auth_code(port)?;
+    let code = get_auth_code(listener, &state)?;
 
     // Exchange code for tokens
-    let tokens = exchange_code_for_tokens(client_id, client_secret, &code, &redirect_uri)?;
+    let tokens =
+        exchange_code_for_tokens(client_id, client_secret, &code, &redirect_uri, &code_verifier)?;
 
     // Save tokens in yup_oauth2 format
-    save_tokens(token_file, &tokens)?;
+    save_tokens(token_store, &tokens)?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_url: String,
+    expires_in: u64,
+    interval: u64,
+}
+
+/// Response shape shared by both polling outcomes: either a token response, or an OAuth `error`
+/// such as `authorization_pending`/`slow_down` while the user hasn't finished on the other
+/// device yet. Modeled as one struct with everything optional, since which fields are present is
+/// exactly what distinguishes the two outcomes.
+#[derive(Deserialize)]
+struct DevicePollResponse {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+    #[allow(dead_code)]
+    token_type: Option<String>,
+    error: Option<String>,
+}
+
+/// Performs OAuth login via the Device Authorization Grant (RFC 8628).
+///
+/// Unlike `headless_login`, this needs no local TCP listener and works behind any firewall: the
+/// user is given a short code to enter on any other device with a browser, while this process
+/// polls Google until that device confirms it.
+pub fn device_login(
+    client_id: &str,
+    client_secret: &str,
+    token_store: &TokenStore,
+) -> Result<(), Error> {
+    let client = reqwest::blocking::Client::new();
+
+    let device_code_response: DeviceCodeResponse = client
+        .post("https://oauth2.googleapis.com/device/code")
+        .form(&[
+            ("client_id", client_id),
+            ("scope", "https://www.googleapis.com/auth/drive"),
+        ])
+        .send()
+        .map_err(|e| err_msg(format!("Device code request failed: {}", e)))?
+        .json()
+        .map_err(|e| err_msg(format!("Failed to parse device code response: {}", e)))?;
+
+    println!("\n=== GCSF Authentication ===\n");
+    println!("To authorize GCSF, visit:\n");
+    println!("  {}\n", device_code_response.verification_url);
+    println!("and enter this code:\n");
+    println!("  {}\n", device_code_response.user_code);
+
+    let tokens = poll_for_device_tokens(client_id, client_secret, &device_code_response)?;
+    save_tokens(token_store, &tokens)?;
 
     Ok(())
 }
 
-/// Waits for auth code from either localhost redirect or stdin paste.
-fn get_auth_code(port: u16) -> Result<String, Error> {
+/// Polls the token endpoint every `interval` seconds (growing by 5s on `slow_down`) until the
+/// user finishes authorizing on the other device, or `expires_in` seconds have passed.
+fn poll_for_device_tokens(
+    client_id: &str,
+    client_secret: &str,
+    device_code_response: &DeviceCodeResponse,
+) -> Result<TokenResponse, Error> {
+    let client = reqwest::blocking::Client::new();
+    let deadline = std::time::Instant::now() + Duration::from_secs(device_code_response.expires_in);
+    let mut interval = Duration::from_secs(device_code_response.interval);
+
+    loop {
+        if std::time::Instant::now() >= deadline {
+            return Err(err_msg("Device authorization timed out before it was confirmed"));
+        }
+
+        thread::sleep(interval);
+
+        let response: DevicePollResponse = client
+            .post("https://oauth2.googleapis.com/token")
+            .form(&[
+                ("client_id", client_id),
+                ("client_secret", client_secret),
+                ("device_code", device_code_response.device_code.as_str()),
+                (
+                    "grant_type",
+                    "urn:ietf:params:oauth:grant-type:device_code",
+                ),
+            ])
+            .send()
+            .map_err(|e| err_msg(format!("Token poll failed: {}", e)))?
+            .json()
+            .map_err(|e| err_msg(format!("Failed to parse token poll response: {}", e)))?;
+
+        match (response.access_token, response.error.as_deref()) {
+            (Some(access_token), _) => {
+                println!("\nAuthorization confirmed!");
+                return Ok(TokenResponse {
+                    access_token,
+                    refresh_token: response.refresh_token,
+                    expires_in: response.expires_in,
+                    token_type: response.token_type.unwrap_or_default(),
+                });
+            }
+            (None, Some("authorization_pending")) => continue,
+            (None, Some("slow_down")) => interval += Duration::from_secs(5),
+            (None, Some(other)) => return Err(err_msg(format!("Device authorization failed: {}", other))),
+            (None, None) => return Err(err_msg("Device authorization failed: empty response")),
+        }
+    }
+}
+
+/// Binds the redirect listener to the first of `candidate_ports` that's actually free, returning
+/// the bound listener along with the port it landed on. A stale GCSF process (or anything else)
+/// holding onto one port shouldn't prevent login.
+fn bind_redirect_listener(candidate_ports: &[u16]) -> Result<(TcpListener, u16), Error> {
+    for &port in candidate_ports {
+        if let Ok(listener) = TcpListener::bind(format!("127.0.0.1:{}", port)) {
+            return Ok((listener, port));
+        }
+    }
+
+    Err(err_msg(format!(
+        "Could not bind the OAuth redirect listener to any of {:?}",
+        candidate_ports
+    )))
+}
+
+/// Waits for auth code from either localhost redirect or stdin paste. Any redirect whose `state`
+/// doesn't match `expected_state` is rejected (and the listener keeps waiting for a legitimate
+/// one) rather than accepted, closing a login-CSRF hole where a crafted redirect URL could
+/// otherwise inject an attacker's authorization code.
+fn get_auth_code(listener: TcpListener, expected_state: &str) -> Result<String, Error> {
     let (tx, rx) = mpsc::channel::<Result<String, String>>();
 
     // Spawn thread to listen for HTTP redirect
     let tx_http = tx.clone();
+    let expected_state_http = expected_state.to_string();
     thread::spawn(move || {
-        if let Ok(listener) = TcpListener::bind(format!("127.0.0.1:{}", port)) {
-            listener.set_nonblocking(false).ok();
-            if let Ok((mut stream, _)) = listener.accept() {
-                let mut reader = BufReader::new(&stream);
-                let mut request_line = String::new();
-                if reader.read_line(&mut request_line).is_ok() {
-                    // Parse: GET /?code=xxx&scope=... HTTP/1.1
-                    if let Some(code) = extract_code_from_request(&request_line) {
-                        // Send success response to browser
-                        let response = "HTTP/1.1 200 OK\r\n\
+        listener.set_nonblocking(false).ok();
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_ok() {
+                // Parse: GET /?code=xxx&state=yyy&scope=... HTTP/1.1
+                if let Some((code, state)) = extract_code_from_request(&request_line) {
+                    if state.as_deref() != Some(expected_state_http.as_str()) {
+                        let response = "HTTP/1.1 400 Bad Request\r\n\
                             Content-Type: text/html\r\n\r\n\
-                            <html><body><h1>Success!</h1>\
-                            <p>You can close this window and return to GCSF.</p>\
-                            </body></html>";
+                            <html><body><h1>Invalid state</h1>\
+                            <p>This redirect does not match the request that started it. \
+                            Please restart authentication.</p></body></html>";
                         stream.write_all(response.as_bytes()).ok();
-                        tx_http.send(Ok(code)).ok();
-                        return;
+                        continue;
                     }
+
+                    // Send success response to browser
+                    let response = "HTTP/1.1 200 OK\r\n\
+                        Content-Type: text/html\r\n\r\n\
+                        <html><body><h1>Success!</h1>\
+                        <p>You can close this window and return to GCSF.</p>\
+                        </body></html>";
+                    stream.write_all(response.as_bytes()).ok();
+                    tx_http.send(Ok(code)).ok();
+                    return;
                 }
             }
         }
@@ -119,6 +292,7 @@ fn get_auth_code(port: u16) -> Result<String, Error> {
 
     // Spawn thread to read from stdin
     let tx_stdin = tx;
+    let expected_state_stdin = expected_state.to_string();
     thread::spawn(move || {
         print!("Paste redirect URL here (or wait for automatic redirect): ");
         std::io::stdout().flush().ok();
@@ -129,13 +303,21 @@ fn get_auth_code(port: u16) -> Result<String, Error> {
             if trimmed.is_empty() {
                 continue;
             }
-            if let Some(code) = extract_code_from_url(trimmed) {
-                tx_stdin.send(Ok(code)).ok();
-                return;
-            } else {
-                println!("Could not find 'code' parameter in URL. Please try again.");
-                print!("Paste redirect URL: ");
-                std::io::stdout().flush().ok();
+            match extract_code_from_url(trimmed) {
+                Some((code, state)) if state.as_deref() == Some(expected_state_stdin.as_str()) => {
+                    tx_stdin.send(Ok(code)).ok();
+                    return;
+                }
+                Some(_) => {
+                    println!("'state' parameter does not match. Please try again.");
+                    print!("Paste redirect URL: ");
+                    std::io::stdout().flush().ok();
+                }
+                None => {
+                    println!("Could not find 'code' parameter in URL. Please try again.");
+                    print!("Paste redirect URL: ");
+                    std::io::stdout().flush().ok();
+                }
             }
         }
     });
@@ -151,8 +333,32 @@ fn get_auth_code(port: u16) -> Result<String, Error> {
     }
 }
 
-/// Extract code from HTTP request line: "GET /?code=xxx&scope=... HTTP/1.1"
-fn extract_code_from_request(request_line: &str) -> Option<String> {
+/// Generates a PKCE `(code_verifier, code_challenge)` pair per RFC 7636: a 32-byte random
+/// verifier, base64url-no-pad encoded (yielding the required 43-128 URL-safe characters), and
+/// its S256 challenge (base64url-no-pad of the verifier's SHA-256 digest). PKCE makes the
+/// authorization-code exchange safe even though an installed app's `client_secret` isn't really
+/// secret.
+fn generate_pkce_pair() -> (String, String) {
+    let mut verifier_bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut verifier_bytes);
+    let code_verifier = URL_SAFE_NO_PAD.encode(verifier_bytes);
+
+    let digest = Sha256::digest(code_verifier.as_bytes());
+    let code_challenge = URL_SAFE_NO_PAD.encode(digest);
+
+    (code_verifier, code_challenge)
+}
+
+/// Generates a random, high-entropy CSRF `state` token: 32 random bytes, base64url-no-pad
+/// encoded, to be echoed back on the OAuth redirect and checked in `get_auth_code`.
+fn generate_state_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Extract `(code, state)` from HTTP request line: "GET /?code=xxx&state=yyy&scope=... HTTP/1.1"
+fn extract_code_from_request(request_line: &str) -> Option<(String, Option<String>)> {
     let parts: Vec<&str> = request_line.split_whitespace().collect();
     if parts.len() >= 2 {
         let path = parts[1];
@@ -163,13 +369,28 @@ fn extract_code_from_request(request_line: &str) -> Option<String> {
     }
 }
 
-/// Extract code from full URL or path with query string
-fn extract_code_from_url(url_str: &str) -> Option<String> {
-    Url::parse(url_str).ok().and_then(|url| {
-        url.query_pairs()
-            .find(|(key, _)| key == "code")
-            .map(|(_, value)| value.to_string())
-    })
+/// Extract `(code, state)` from full URL or path with query string. `code` must be present;
+/// `state` is returned whether or not it's present so the caller can decide what to do about a
+/// missing/mismatched one.
+fn extract_code_from_url(url_str: &str) -> Option<(String, Option<String>)> {
+    let url = Url::parse(url_str).ok()?;
+    let code = url
+        .query_pairs()
+        .find(|(key, _)| key == "code")
+        .map(|(_, value)| value.to_string())?;
+    let state = url
+        .query_pairs()
+        .find(|(key, _)| key == "state")
+        .map(|(_, value)| value.to_string());
+
+    Some((code, state))
+}
+
+/// Test-only window onto `extract_code_from_url`, so the redirect-parsing/state-matching logic
+/// exercised in `get_auth_code` can be unit-tested without standing up a listener.
+#[cfg(test)]
+pub fn extract_code_from_url_for_testing(url_str: &str) -> Option<(String, Option<String>)> {
+    extract_code_from_url(url_str)
 }
 
 /// Exchange authorization code for access/refresh tokens
@@ -178,6 +399,7 @@ fn exchange_code_for_tokens(
     client_secret: &str,
     code: &str,
     redirect_uri: &str,
+    code_verifier: &str,
 ) -> Result<TokenResponse, Error> {
     let client = reqwest::blocking::Client::new();
 
@@ -189,6 +411,7 @@ fn exchange_code_for_tokens(
             ("code", code),
             ("grant_type", "authorization_code"),
             ("redirect_uri", redirect_uri),
+            ("code_verifier", code_verifier),
         ])
         .send()
         .map_err(|e| err_msg(format!("Token request failed: {}", e)))?;
@@ -203,8 +426,39 @@ fn exchange_code_for_tokens(
     }
 }
 
-/// Save tokens in yup_oauth2 compatible JSON format
-fn save_tokens(path: &Path, tokens: &TokenResponse) -> Result<(), Error> {
+/// Where the yup_oauth2-compatible token blob is persisted. `File` keeps the previous behavior
+/// (a plaintext JSON file); `Keyring` hands the same blob to the platform secret store (Secret
+/// Service / macOS Keychain / Windows Credential Manager) instead, so a long-lived refresh token
+/// doesn't have to sit on disk.
+pub enum TokenStore {
+    File(PathBuf),
+    Keyring { service: String, account: String },
+}
+
+impl TokenStore {
+    fn write(&self, blob: &str) -> Result<(), Error> {
+        match self {
+            TokenStore::File(path) => std::fs::write(path, blob)
+                .map_err(|e| err_msg(format!("Failed to write token file: {}", e))),
+            TokenStore::Keyring { service, account } => keyring::Entry::new(service, account)
+                .and_then(|entry| entry.set_password(blob))
+                .map_err(|e| err_msg(format!("Failed to write tokens to keyring: {}", e))),
+        }
+    }
+
+    fn read(&self) -> Result<String, Error> {
+        match self {
+            TokenStore::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| err_msg(format!("Failed to read token file: {}", e))),
+            TokenStore::Keyring { service, account } => keyring::Entry::new(service, account)
+                .and_then(|entry| entry.get_password())
+                .map_err(|e| err_msg(format!("Failed to read tokens from keyring: {}", e))),
+        }
+    }
+}
+
+/// Save tokens in yup_oauth2 compatible JSON format, to whichever backend `store` points at.
+fn save_tokens(store: &TokenStore, tokens: &TokenResponse) -> Result<(), Error> {
     // Calculate expiration time
     let now = OffsetDateTime::now_utc();
     let expires_in_secs = tokens.expires_in.unwrap_or(3600) as i64;
@@ -234,8 +488,189 @@ fn save_tokens(path: &Path, tokens: &TokenResponse) -> Result<(), Error> {
     let json = serde_json::to_string(&vec![entry])
         .map_err(|e| err_msg(format!("Failed to serialize tokens: {}", e)))?;
 
-    std::fs::write(path, json)
-        .map_err(|e| err_msg(format!("Failed to write token file: {}", e)))?;
+    store.write(&json)
+}
 
-    Ok(())
+/// Loads the raw yup_oauth2-compatible token blob previously written by `save_tokens`, from
+/// whichever backend `store` points at.
+pub fn load_tokens(store: &TokenStore) -> Result<String, Error> {
+    store.read()
+}
+
+/// Pulls just the access token and its expiry out of whatever `load_tokens` returns, without
+/// exposing this module's storage format to callers. Used by `DriveFacade`'s token cache to pick
+/// up a freshly (re)written token after `refresh_access_token`/`refresh_service_account_token`
+/// without re-deriving this module's token lifecycle logic itself.
+pub fn cached_access_token(store: &TokenStore) -> Result<(String, OffsetDateTime), Error> {
+    let blob = load_tokens(store)?;
+    let mut entries: Vec<StoredTokenEntry> = serde_json::from_str(&blob)
+        .map_err(|e| err_msg(format!("Failed to parse stored tokens: {}", e)))?;
+    let entry = entries
+        .pop()
+        .ok_or_else(|| err_msg("No stored token entry"))?;
+    let expires_at = entry.token.expires_at()?;
+
+    Ok((entry.token.access_token, expires_at))
+}
+
+/// How close to expiry a stored access token has to be before `refresh_access_token` warns that
+/// re-authorization may soon be required.
+const EXPIRY_WARNING_THRESHOLD: time::Duration = time::Duration::days(2);
+
+/// Refreshes an expired (or soon-to-expire) access token from the stored refresh token, so a
+/// long-running mount doesn't have to fall back to interactive login every time the short-lived
+/// access token lapses. Google omits `refresh_token` from refresh responses, so the previously
+/// stored one is preserved in the rewritten entry.
+pub fn refresh_access_token(
+    client_id: &str,
+    client_secret: &str,
+    store: &TokenStore,
+) -> Result<(), Error> {
+    let blob = load_tokens(store)?;
+    let mut entries: Vec<StoredTokenEntry> = serde_json::from_str(&blob)
+        .map_err(|e| err_msg(format!("Failed to parse stored tokens: {}", e)))?;
+    let entry = entries
+        .pop()
+        .ok_or_else(|| err_msg("No stored token entry to refresh"))?;
+
+    if let Ok(expires_at) = entry.token.expires_at() {
+        if OffsetDateTime::now_utc() + EXPIRY_WARNING_THRESHOLD >= expires_at {
+            warn!(
+                "Stored access token expires at {:?}; re-authorization may soon be required.",
+                expires_at
+            );
+        }
+    }
+
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post("https://oauth2.googleapis.com/token")
+        .form(&[
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("refresh_token", entry.token.refresh_token.as_str()),
+            ("grant_type", "refresh_token"),
+        ])
+        .send()
+        .map_err(|e| err_msg(format!("Token refresh request failed: {}", e)))?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().unwrap_or_default();
+        return Err(err_msg(format!("Token refresh failed: {}", error_text)));
+    }
+
+    let refreshed: TokenResponse = response
+        .json()
+        .map_err(|e| err_msg(format!("Failed to parse token refresh response: {}", e)))?;
+
+    let tokens = TokenResponse {
+        access_token: refreshed.access_token,
+        refresh_token: refreshed
+            .refresh_token
+            .or(Some(entry.token.refresh_token)),
+        expires_in: refreshed.expires_in,
+        token_type: refreshed.token_type,
+    };
+
+    save_tokens(store, &tokens)
+}
+
+/// The fields this module actually needs out of a Google service-account JSON key; the real file
+/// has several more (`project_id`, `private_key_id`, `client_id`, ...) that nothing here reads.
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+/// Claims for the JWT bearer grant (RFC 7523) a service account trades for an access token, with
+/// no human or redirect involved.
+#[derive(Serialize)]
+struct ServiceAccountClaims {
+    iss: String,
+    scope: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+}
+
+/// Authenticates as a service account: parses `key_json` (the JSON key downloaded from the Google
+/// Cloud console), mints an access token via the JWT bearer grant, and saves it to `token_store`
+/// in the same format `headless_login`/`device_login` use, so `DriveFacade` doesn't need to care
+/// which flow produced the stored token.
+///
+/// Unlike the interactive flows, this never blocks on a human: `gcsf mount` can run this on every
+/// invocation since minting a fresh token is cheap, but it's also split out from
+/// `refresh_service_account_token` so a caller holding a still-valid token doesn't pay a network
+/// round-trip it doesn't need.
+pub fn service_account_login(key_json: &str, token_store: &TokenStore) -> Result<(), Error> {
+    let key: ServiceAccountKey = serde_json::from_str(key_json)
+        .map_err(|e| err_msg(format!("Failed to parse service account key: {}", e)))?;
+    let tokens = request_service_account_token(&key)?;
+    save_tokens(token_store, &tokens)
+}
+
+/// Mints a new service-account access token if the one already in `token_store` is missing or
+/// expired, otherwise leaves it alone. Service accounts have no long-lived refresh token to trade
+/// in like `refresh_access_token` does: the JWT bearer grant mints a brand new token every time,
+/// so "refreshing" here just means deciding whether that's necessary yet.
+pub fn refresh_service_account_token(key_json: &str, token_store: &TokenStore) -> Result<(), Error> {
+    let still_valid = load_tokens(token_store)
+        .ok()
+        .and_then(|blob| serde_json::from_str::<Vec<StoredTokenEntry>>(&blob).ok())
+        .and_then(|mut entries| entries.pop())
+        .and_then(|entry| entry.token.expires_at().ok())
+        .is_some_and(|expires_at| OffsetDateTime::now_utc() < expires_at);
+
+    if still_valid {
+        return Ok(());
+    }
+
+    service_account_login(key_json, token_store)
+}
+
+/// Performs the actual JWT bearer grant (RFC 7523): builds a claim set scoped to Drive and
+/// good for one hour, signs it with the service account's RSA private key, and trades it for an
+/// access token at `key.token_uri`.
+fn request_service_account_token(key: &ServiceAccountKey) -> Result<TokenResponse, Error> {
+    let now = OffsetDateTime::now_utc();
+    let claims = ServiceAccountClaims {
+        iss: key.client_email.clone(),
+        scope: "https://www.googleapis.com/auth/drive".to_string(),
+        aud: key.token_uri.clone(),
+        iat: now.unix_timestamp(),
+        exp: (now + time::Duration::HOUR).unix_timestamp(),
+    };
+
+    let encoding_key = jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+        .map_err(|e| err_msg(format!("Invalid service account private key: {}", e)))?;
+    let assertion = jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+        &claims,
+        &encoding_key,
+    )
+    .map_err(|e| err_msg(format!("Failed to sign service account JWT: {}", e)))?;
+
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .post(&key.token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ])
+        .send()
+        .map_err(|e| err_msg(format!("Service account token request failed: {}", e)))?;
+
+    if !response.status().is_success() {
+        let error_text = response.text().unwrap_or_default();
+        return Err(err_msg(format!(
+            "Service account authentication failed: {}",
+            error_text
+        )));
+    }
+
+    response
+        .json::<TokenResponse>()
+        .map_err(|e| err_msg(format!("Failed to parse service account token response: {}", e)))
 }
@@ -1,6 +1,26 @@
+use super::auth::TokenStore;
+use super::file_manager::DuplicatePolicy;
+use std::cmp;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+lazy_static! {
+    /// The export format used for a Google-native document when the user hasn't overridden it via
+    /// `Config::export_formats`. Chosen to be broadly editable rather than archival (Office Open
+    /// XML/CSV over ODF/plain text), since `docx`/`xlsx` are what most desktop tools expect.
+    static ref DEFAULT_EXPORT_FORMATS: HashMap<&'static str, &'static str> = hashmap! {
+        "application/vnd.google-apps.document" =>
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "application/vnd.google-apps.presentation" =>
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "application/vnd.google-apps.spreadsheet" =>
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "application/vnd.google-apps.drawing" => "image/png",
+        "application/vnd.google-apps.site" => "text/plain",
+    };
+}
+
 /// Provides a few properties of the file system that can be configured. Includes sensible
 /// defaults for the absent values.
 #[derive(Deserialize, Clone, Debug, Default)]
@@ -9,10 +29,13 @@ pub struct Config {
     pub debug: Option<bool>,
     /// Perform a mount check and fail early if it fails.
     pub mount_check: Option<bool>,
-    /// How long to cache the contents of a file after it has been accessed.
-    pub cache_max_seconds: Option<u64>,
-    /// How how many files to cache.
-    pub cache_max_items: Option<u64>,
+    /// How many bytes of downloaded file content (across every cached byte range, for every
+    /// file) to keep in memory before evicting every cached range of the least-recently-touched
+    /// files.
+    pub cache_max_bytes: Option<u64>,
+    /// Size, in bytes, of each chunk `DriveFacade::flush` PUTs during a resumable upload. Must be
+    /// a multiple of 256 KiB per Drive's resumable upload protocol.
+    pub upload_chunk_size_bytes: Option<u64>,
     /// How long to cache the size and capacity of the file system.
     pub cache_statfs_seconds: Option<u64>,
     /// How many seconds to wait before checking for remote changes and updating them locally.
@@ -35,6 +58,59 @@ pub struct Config {
     pub client_secret: Option<String>,
     /// Port for OAuth redirect during authentication.
     pub auth_port: Option<u16>,
+    /// Regex patterns matched against a file's full path within the tree. A file (or any file
+    /// under an excluded directory) is not mounted if it matches one of these.
+    pub excludes: Option<Vec<String>>,
+    /// Regex patterns matched against a file's full path within the tree. If non-empty, only
+    /// files matching at least one of these are mounted. `excludes` takes precedence.
+    pub includes: Option<Vec<String>>,
+    /// How to automatically resolve groups of content-identical files (same size and
+    /// `md5Checksum`): `"keep_newest"`, `"keep_oldest"`, or unset/anything else for `"none"`
+    /// (never touch them). Opt-in because this moves files to Drive's trash.
+    pub duplicate_policy: Option<String>,
+    /// Overrides the format a Google-native document (Docs/Sheets/Slides/Drawings/Sites) is
+    /// exported as, keyed by the Google MIME type (e.g. `application/vnd.google-apps.document`)
+    /// and valued by the concrete MIME type to export to (e.g. a `.docx`/`.pdf`/`.odt` MIME
+    /// type). Unlisted Google mime types fall back to `DEFAULT_EXPORT_FORMATS`.
+    pub export_formats: Option<HashMap<String, String>>,
+    /// If set to true, every Google-native document is additionally presented as one sibling file
+    /// per entry in `DriveFacade::export_alternatives` (e.g. `report.odt`, `report.docx`,
+    /// `report.pdf`), instead of just the single format `export_formats` picked as the default.
+    /// Off by default since it multiplies the file count for every Google-native document mounted.
+    pub export_all_formats: Option<bool>,
+    /// If set to true, OAuth tokens are stored in the platform secret store (Secret Service /
+    /// macOS Keychain / Windows Credential Manager) via the `keyring` crate instead of in a
+    /// plaintext JSON file under `config_dir`. Defaults to false, to keep the existing behavior.
+    pub use_keyring: Option<bool>,
+    /// If set to true, file content is transparently encrypted before upload and decrypted on
+    /// read using a per-mount key from `encryption_key_file`. Off by default, since it's wasted
+    /// effort for anyone who trusts Drive with plaintext already.
+    pub encrypt_content: Option<bool>,
+    /// Where the per-mount content-encryption key lives. Only consulted when `encrypt_content` is
+    /// set; generated on first use if the file doesn't already exist.
+    pub encryption_key_file: Option<PathBuf>,
+    /// How many `modifiedTime` windows `DriveFacade::get_all_files` shards a full file listing
+    /// into, each fetched concurrently on its own connection (see `DriveFacade::time_windows`).
+    pub list_parallelism: Option<u32>,
+    /// Which credential `auth::service_account_login` (`"service_account"`) vs. the interactive
+    /// OAuth flows (anything else, including unset) should use. Set to `"service_account"` for
+    /// headless servers/CI with no human around to click through a login.
+    pub credential_type: Option<String>,
+    /// The service-account JSON key (the whole `{ "type": "service_account", "client_email",
+    /// "private_key", "token_uri", ... }` document, downloaded from the Google Cloud console),
+    /// inline exactly like `client_secret` is. Only consulted when `credential_type` is
+    /// `"service_account"`.
+    pub service_account_key: Option<String>,
+    /// Where whole downloaded file bodies are persisted on disk, behind the in-memory
+    /// `RangeCache`, so they survive past that cache's eviction (or a process restart) without a
+    /// re-download from Drive. Defaults to a session-namespaced directory under `config_dir`.
+    pub disk_cache_dir: Option<PathBuf>,
+    /// How many seconds a disk-cached file body stays valid before it's treated as stale and
+    /// re-fetched from Drive.
+    pub disk_cache_ttl_seconds: Option<u64>,
+    /// Total bytes the disk cache is allowed to hold across every cached file before the
+    /// least-recently-modified entries are evicted.
+    pub disk_cache_max_bytes: Option<u64>,
 }
 
 impl Config {
@@ -48,14 +124,21 @@ impl Config {
         self.mount_check.unwrap_or(true)
     }
 
-    /// How long to cache the contents of a file after it has been accessed.
-    pub fn cache_max_seconds(&self) -> Duration {
-        Duration::from_secs(self.cache_max_seconds.unwrap_or(10))
+    /// How many bytes of downloaded file content to keep cached in memory, across every byte
+    /// range of every file, before the cached ranges of the least-recently-touched files get
+    /// evicted. Defaults to 256 MiB.
+    pub fn cache_max_bytes(&self) -> u64 {
+        self.cache_max_bytes.unwrap_or(256 * 1024 * 1024)
     }
 
-    /// How how many files to cache.
-    pub fn cache_max_items(&self) -> u64 {
-        self.cache_max_items.unwrap_or(10)
+    /// Size, in bytes, of each chunk PUT during a resumable upload. Defaults to 8 MiB, which keeps
+    /// round-trips infrequent while still letting an interrupted upload resume from close to
+    /// where it left off instead of restarting. Rounded down to the nearest multiple of 256 KiB
+    /// (with a floor of 256 KiB), since Drive rejects chunk sizes that aren't.
+    pub fn upload_chunk_size_bytes(&self) -> u64 {
+        const MIN: u64 = 256 * 1024;
+        let requested = self.upload_chunk_size_bytes.unwrap_or(8 * 1024 * 1024);
+        cmp::max(MIN, (requested / MIN) * MIN)
     }
 
     /// How long to cache the size and capacity of the filesystem. These are the values reported by `df`.
@@ -86,11 +169,38 @@ impl Config {
         Path::new(self.config_dir.as_ref().unwrap()).join(Path::new(self.session_name()))
     }
 
+    /// Whether OAuth tokens should be stored in the platform secret store rather than on disk.
+    pub fn use_keyring(&self) -> bool {
+        self.use_keyring.unwrap_or(false)
+    }
+
+    /// The backend `auth::save_tokens`/`auth::load_tokens` should use, chosen via `use_keyring`.
+    /// The keyring account is the session name, so multiple sessions don't clobber each other's
+    /// tokens, mirroring how `token_file`/`snapshot_path` are already namespaced.
+    pub fn token_store(&self) -> TokenStore {
+        if self.use_keyring() {
+            TokenStore::Keyring {
+                service: "gcsf".to_string(),
+                account: self.session_name().clone(),
+            }
+        } else {
+            TokenStore::File(self.token_file())
+        }
+    }
+
     /// The path to the config dir.
     pub fn config_dir(&self) -> &PathBuf {
         self.config_dir.as_ref().unwrap()
     }
 
+    /// Where the compressed inode/file-tree snapshot is persisted between mounts (see
+    /// `FileManager::with_drive_facade_and_snapshot`). Named after the session so that multiple
+    /// sessions sharing a config dir don't clobber each other's snapshot.
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.config_dir()
+            .join(format!("{}.tree.zst", self.session_name()))
+    }
+
     /// If set to true, Google Drive will provide a code after logging in and
     /// authorizing GCSF. This code must be copied and pasted into GCSF in order to
     /// complete the process. Useful for running GCSF on a remote (headless) server.
@@ -127,4 +237,104 @@ impl Config {
     pub fn auth_port(&self) -> u16 {
         self.auth_port.unwrap_or(8081)
     }
+
+    /// Patterns that exclude a file (and everything under it, if it's a directory) from being
+    /// mounted.
+    pub fn excludes(&self) -> Vec<String> {
+        self.excludes.clone().unwrap_or_default()
+    }
+
+    /// Patterns that a file's path must match at least one of, in order to be mounted. Empty
+    /// means every file is allowed through.
+    pub fn includes(&self) -> Vec<String> {
+        self.includes.clone().unwrap_or_default()
+    }
+
+    /// How to automatically resolve groups of content-identical files. Defaults to `None`, and
+    /// falls back to it for any unrecognized value, so a typo in config can't accidentally start
+    /// trashing files.
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        match self.duplicate_policy.as_deref() {
+            Some("keep_newest") => DuplicatePolicy::KeepNewest,
+            Some("keep_oldest") => DuplicatePolicy::KeepOldest,
+            _ => DuplicatePolicy::None,
+        }
+    }
+
+    /// The Google mime type -> export mime type map used to download Google-native documents
+    /// (they have no raw bytes of their own and must be exported). Starts from
+    /// `DEFAULT_EXPORT_FORMATS` and lets `export_formats` override/add entries on top.
+    pub fn export_formats(&self) -> HashMap<String, String> {
+        let mut formats: HashMap<String, String> = DEFAULT_EXPORT_FORMATS
+            .iter()
+            .map(|(&k, &v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        if let Some(ref overrides) = self.export_formats {
+            formats.extend(overrides.clone());
+        }
+
+        formats
+    }
+
+    /// Whether a Google-native document should be presented as several sibling files, one per
+    /// export format, instead of just the configured default.
+    pub fn export_all_formats(&self) -> bool {
+        self.export_all_formats.unwrap_or(false)
+    }
+
+    /// Whether file content should be transparently encrypted before upload and decrypted on read.
+    pub fn encrypt_content(&self) -> bool {
+        self.encrypt_content.unwrap_or(false)
+    }
+
+    /// Where the per-mount content-encryption key is stored, namespaced by session name so that
+    /// multiple sessions sharing a config dir don't clobber each other's key, mirroring
+    /// `snapshot_path`. Only consulted when `encrypt_content` is set.
+    pub fn encryption_key_file(&self) -> PathBuf {
+        self.encryption_key_file.clone().unwrap_or_else(|| {
+            self.config_dir()
+                .join(format!("{}.key", self.session_name()))
+        })
+    }
+
+    /// How many concurrent `modifiedTime` windows to shard a full file listing into. Defaults to
+    /// 4; set to 1 to fall back to the old single serial query.
+    pub fn list_parallelism(&self) -> u32 {
+        cmp::max(1, self.list_parallelism.unwrap_or(4))
+    }
+
+    /// Whether to authenticate via a service account (see `auth::service_account_login`) instead
+    /// of an interactive OAuth flow.
+    pub fn use_service_account(&self) -> bool {
+        self.credential_type.as_deref() == Some("service_account")
+    }
+
+    /// The service-account JSON key. Only consulted when `use_service_account` is set.
+    pub fn service_account_key(&self) -> &String {
+        self.service_account_key
+            .as_ref()
+            .expect("service_account_key must be set when credential_type = \"service_account\"")
+    }
+
+    /// Where whole downloaded file bodies are persisted on disk. Named after the session, like
+    /// `snapshot_path`/`encryption_key_file`, so multiple sessions sharing a config dir don't
+    /// share (or evict) each other's cached content.
+    pub fn disk_cache_dir(&self) -> PathBuf {
+        self.disk_cache_dir.clone().unwrap_or_else(|| {
+            self.config_dir()
+                .join(format!("{}.content_cache", self.session_name()))
+        })
+    }
+
+    /// How long a disk-cached file body stays valid. Defaults to one hour.
+    pub fn disk_cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.disk_cache_ttl_seconds.unwrap_or(60 * 60))
+    }
+
+    /// Total bytes the disk cache may hold before it starts evicting least-recently-modified
+    /// entries. Defaults to 1 GiB.
+    pub fn disk_cache_max_bytes(&self) -> u64 {
+        self.disk_cache_max_bytes.unwrap_or(1024 * 1024 * 1024)
+    }
 }
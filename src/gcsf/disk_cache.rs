@@ -0,0 +1,127 @@
+//! A disk-backed, TTL-expiring cache of fully-fetched file content, so a large Drive file that
+//! has already been downloaded once doesn't have to be re-fetched from Drive just because it
+//! aged out of `drive_facade::RangeCache`'s in-memory budget. Complements rather than replaces
+//! that cache: `DriveFacade` still serves hot reads out of `RangeCache`; `DiskCache` is consulted
+//! on a miss there, and is itself only populated when a full file body is already in hand (a
+//! Google-native export, or `read_plain`'s "server ignored Range" fallback), since chasing
+//! partial ranges through disk I/O would cost more than it saves.
+
+use failure::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Caches whole file bodies on disk under `dir`, one file per cache key, evicting the
+/// least-recently-modified entries once the total size on disk exceeds `max_bytes` and treating
+/// any entry older than `ttl` as if it were never cached.
+pub struct DiskCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf, ttl: Duration, max_bytes: u64) -> Self {
+        DiskCache { dir, ttl, max_bytes }
+    }
+
+    /// Cache keys (Drive ids, optionally suffixed with an export mime type, see
+    /// `DriveFacade::content_cache_key`) are already mostly filesystem-friendly, but are sanitized
+    /// anyway so a crafted key can't escape `dir`, and suffixed with a hash of the unsanitized key
+    /// (same `DefaultHasher`-plus-truncate approach as `SuffixScheme::DriveIdHash`) so two keys
+    /// that sanitize to the same string -- e.g. `"a:b"` and `"a_b"`, both of which collapse `:`/`_`
+    /// the same way -- don't collide onto the same file.
+    fn path_for(&self, key: &str) -> PathBuf {
+        let sanitized: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = format!("{:x}", hasher.finish())
+            .chars()
+            .take(8)
+            .collect::<String>();
+
+        self.dir.join(format!("{}-{}", sanitized, hash))
+    }
+
+    /// Returns `key`'s cached content if it's on disk and still within `ttl`, evicting it instead
+    /// if it has expired.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path_for(key);
+        let metadata = fs::metadata(&path).ok()?;
+        let modified = metadata.modified().ok()?;
+
+        if modified.elapsed().unwrap_or(Duration::from_secs(0)) > self.ttl {
+            let _ = fs::remove_file(&path);
+            return None;
+        }
+
+        fs::read(&path).ok()
+    }
+
+    /// Persists `data` under `key`, creating `dir` if needed, then evicts least-recently-modified
+    /// entries until the directory's total size is back under `max_bytes`. Writes to a sibling
+    /// temp file and renames it into place, so a process getting killed mid-write can never leave
+    /// a truncated file behind for `get` to serve as if it were the real, complete content.
+    pub fn insert(&self, key: &str, data: &[u8]) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+
+        let path = self.path_for(key);
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &path)?;
+
+        self.evict()?;
+        Ok(())
+    }
+
+    /// Drops `key`'s cached content, if any, e.g. because it's about to be overwritten by a
+    /// pending write/flush and would otherwise serve stale content.
+    pub fn remove(&self, key: &str) {
+        let _ = fs::remove_file(self.path_for(key));
+    }
+
+    fn evict(&self) -> Result<(), Error> {
+        let mut entries: Vec<(PathBuf, SystemTime, u64)> = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let metadata = entry.metadata().ok()?;
+                    let modified = metadata.modified().ok()?;
+                    Some((entry.path(), modified, metadata.len()))
+                })
+                .collect(),
+            Err(_) => return Ok(()),
+        };
+
+        let mut total: u64 = entries.iter().map(|(_, _, size)| size).sum();
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(_, modified, _)| *modified);
+        for (path, _, size) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total = total.saturating_sub(size);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,18 +1,37 @@
+use super::auth;
+use super::disk_cache::DiskCache;
+use super::encryption;
 use super::Config;
 use drive3;
 use failure::{err_msg, Error};
 use hyper;
 use hyper::client::Response;
-use lru_time_cache::LruCache;
 use mime_sniffer::MimeTypeSniffer;
 use oauth2;
+use serde::{Deserialize, Serialize};
 use serde_json;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
 use std::io;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use time::OffsetDateTime;
 
 const PAGE_SIZE: i32 = 1000;
+
+/// Directory holding the scratch files `flush` assembles a file's new content into before
+/// uploading it, so a multi-gigabyte edit streams through disk instead of RAM.
+const UPLOAD_SCRATCH_DIR: &str = "/tmp/gcsf_upload_scratch";
+
+/// How many times `flush` rebuilds and re-uploads a file's content before giving up on a
+/// `FlushConflict`.
+const MAX_FLUSH_ATTEMPTS: u32 = 3;
 type DriveId = String;
 type DriveIdRef<'a> = &'a str;
 
@@ -24,6 +43,17 @@ type GcAuthenticator = oauth2::Authenticator<
 >;
 type GcDriveHub = drive3::DriveHub<GcClient, GcAuthenticator>;
 
+/// How close to expiry a cached access token has to be before `DriveFacade::access_token` treats
+/// it as stale and refreshes it rather than handing it out.
+const TOKEN_REFRESH_MARGIN: time::Duration = time::Duration::seconds(60);
+
+/// An access token cached in memory by `DriveFacade::access_token`, alongside the instant it
+/// stops being usable.
+struct CachedToken {
+    access_token: String,
+    expires_at: OffsetDateTime,
+}
+
 /// Provides a simple high-level interface for interacting with the Google Drive API.
 pub struct DriveFacade {
     /// The `drive3::DriveHub` hub used for interacting with the API.
@@ -35,16 +65,66 @@ pub struct DriveFacade {
     /// Maps Drive IDs to a list of pending write operations that must be applied on them.
     pending_writes: HashMap<DriveId, Vec<PendingWrite>>,
 
-    /// The LRU cache used for storing the file contents for any given Drive ID.
-    cache: LruCache<DriveId, Vec<u8>>,
+    /// Sparse, range-granular cache of downloaded file content, keyed by `content_cache_key`, so a
+    /// small read against a huge file only ever has to store (and evict) the bytes it actually
+    /// touched instead of the whole file.
+    cache: RangeCache,
+
+    /// Second-tier cache behind `cache`: whole file bodies persisted to disk with a TTL, so a
+    /// file that aged out of `cache`'s in-memory budget (or a fresh process after a restart)
+    /// doesn't have to hit Drive again for content it already downloaded recently. Consulted on
+    /// every miss in `cache`, but only populated where a full file body is already in hand -- see
+    /// `disk_cache`'s doc comment.
+    disk_cache: DiskCache,
 
     /// Keeps track of the page token used for receiving changes from the `changes.list` API endpoint.
     changes_token: Option<String>,
 
     /// The root id is only stored once, effectively caching the root id.
     root_id: Option<String>,
+
+    /// Google mime type -> export mime type, consulted by `export_mime_type` to pick the default
+    /// format a Google-native document is downloaded as (see `Config::export_formats`).
+    export_formats: HashMap<String, String>,
+
+    /// Kept around so `get_file_range` can mint a bearer token on demand (see `access_token`);
+    /// `hub` doesn't expose the authenticator it was built with.
+    config: Config,
+
+    /// The per-mount content-encryption key, loaded (or generated) from `Config::encryption_key_file`
+    /// when `Config::encrypt_content` is set. `None` disables transparent encryption entirely, in
+    /// which case `read`/`flush` behave exactly as before this feature existed.
+    key_store: Option<encryption::KeyStore>,
+
+    /// In-memory cache for the bearer token `access_token` hands out, so a burst of calls within
+    /// the same token's lifetime don't each re-read the token file or, once it's near expiry,
+    /// each race to refresh it independently (see `access_token`).
+    cached_token: Mutex<Option<CachedToken>>,
+}
+
+/// Returned (wrapped in `Error`) by `flush` when the remote file's `modifiedTime` changed out from
+/// under a read-modify-write and retrying didn't resolve it within `MAX_FLUSH_ATTEMPTS`. Drive's
+/// API has no true conditional-write primitive to prevent this outright, so this is the best
+/// `flush` can do: notice the clash and give up loudly instead of silently clobbering the other
+/// writer. `downcast_ref`-able so callers like `Filesystem::release` can report something more
+/// specific than a generic remote-API failure.
+#[derive(Debug)]
+pub struct FlushConflict {
+    pub id: DriveId,
+}
+
+impl fmt::Display for FlushConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "remote content for {} changed during flush and retries were exhausted",
+            self.id
+        )
+    }
 }
 
+impl StdError for FlushConflict {}
+
 /// Represents a write operation that has been performed from the user's point of view but has not
 /// yet been applied to the local or remote file.
 #[derive(Debug)]
@@ -54,14 +134,144 @@ struct PendingWrite {
     data: Vec<u8>,
 }
 
-lazy_static! {
-    static ref MIME_TYPES: HashMap<&'static str, &'static str> = hashmap! {
-        "application/vnd.google-apps.document" => "application/vnd.oasis.opendocument.text",
-        "application/vnd.google-apps.presentation" => "application/vnd.oasis.opendocument.presentation",
-        "application/vnd.google-apps.spreadsheet" => "application/vnd.oasis.opendocument.spreadsheet",
-        "application/vnd.google-apps.drawing" => "image/png",
-        "application/vnd.google-apps.site" => "text/plain",
-    };
+/// The result of a ranged `alt=media` request: the bytes the server actually sent back, and
+/// whether it honored the `Range` header (`206 Partial Content`) or ignored it and returned the
+/// whole file (`200 OK`).
+struct RangeResponse {
+    data: Vec<u8>,
+    partial: bool,
+}
+
+/// A Drive permission grant, as surfaced through the `user.gcsf.share` xattr.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Permission {
+    /// The permission id, used to `remove_permission` it later. Absent when describing a desired
+    /// grant that doesn't exist yet (see `DriveFacade::reconcile_permissions`).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<String>,
+    /// `owner`/`organizer`/`fileOrganizer`/`writer`/`commenter`/`reader`.
+    pub role: String,
+    /// `user`/`group`/`domain`/`anyone`.
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// The email address being granted access, for `type_` `user`/`group`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub email_address: Option<String>,
+    /// The domain being granted access, for `type_` `domain`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub domain: Option<String>,
+}
+
+/// A sparse cache of already-downloaded byte ranges, keyed by `content_cache_key`. Replaces a
+/// whole-file `LruCache<DriveId, Vec<u8>>`: a read only ever stores the bytes it actually asked
+/// for, touching or overlapping ranges are merged into one, and eviction is driven by total bytes
+/// held rather than by entry count, so a single large file can't silently get evicted just because
+/// it's "one item too many".
+struct RangeCache {
+    /// Per-key, sorted, non-overlapping ranges, as `(start, end_exclusive, data)`.
+    ranges: HashMap<String, Vec<(usize, usize, Vec<u8>)>>,
+    /// Keys in least-to-most-recently-touched order, consulted by `evict` when `total_bytes`
+    /// exceeds `max_bytes`.
+    recency: VecDeque<String>,
+    total_bytes: usize,
+    max_bytes: usize,
+}
+
+impl RangeCache {
+    fn new(max_bytes: usize) -> Self {
+        RangeCache {
+            ranges: HashMap::new(),
+            recency: VecDeque::new(),
+            total_bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Returns `[offset, offset+size)` if that whole span is already covered by one merged range
+    /// under `key`.
+    fn get(&mut self, key: &str, offset: usize, size: usize) -> Option<Vec<u8>> {
+        let end = offset + size;
+        let found = self.ranges.get(key).and_then(|ranges| {
+            ranges
+                .iter()
+                .find(|(start, range_end, _)| *start <= offset && end <= *range_end)
+                .map(|(start, _, data)| data[offset - start..end - start].to_vec())
+        });
+
+        if found.is_some() {
+            self.touch(key);
+        }
+        found
+    }
+
+    /// Records `data` as covering `[offset, offset+data.len())` under `key`, merging it with any
+    /// range it overlaps or is contiguous with (new bytes win where they overlap something
+    /// stale), then evicts the least-recently-touched keys until back under `max_bytes`.
+    fn insert(&mut self, key: &str, offset: usize, data: Vec<u8>) {
+        if data.is_empty() {
+            return;
+        }
+        let end = offset + data.len();
+        let existing = self.ranges.entry(key.to_string()).or_insert_with(Vec::new);
+
+        let mut merged_start = offset;
+        let mut merged_end = end;
+        let mut absorbed = Vec::new();
+        let mut kept = Vec::new();
+
+        for (r_start, r_end, r_data) in existing.drain(..) {
+            if r_end < merged_start || r_start > merged_end {
+                kept.push((r_start, r_end, r_data));
+            } else {
+                merged_start = cmp::min(merged_start, r_start);
+                merged_end = cmp::max(merged_end, r_end);
+                self.total_bytes -= r_data.len();
+                absorbed.push((r_start, r_data));
+            }
+        }
+
+        let mut merged_data = vec![0u8; merged_end - merged_start];
+        for (r_start, r_data) in absorbed {
+            let rel = r_start - merged_start;
+            merged_data[rel..rel + r_data.len()].copy_from_slice(&r_data);
+        }
+        let rel = offset - merged_start;
+        merged_data[rel..rel + data.len()].copy_from_slice(&data);
+
+        self.total_bytes += merged_data.len();
+        kept.push((merged_start, merged_end, merged_data));
+        kept.sort_by_key(|(start, _, _)| *start);
+        *existing = kept;
+
+        self.touch(key);
+        self.evict();
+    }
+
+    /// Drops every range cached under `key`, e.g. because it's about to be overwritten by a
+    /// pending write/flush and would otherwise serve stale content.
+    fn remove(&mut self, key: &str) {
+        if let Some(ranges) = self.ranges.remove(key) {
+            self.total_bytes -= ranges.iter().map(|(_, _, data)| data.len()).sum::<usize>();
+        }
+        self.recency.retain(|k| k != key);
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.recency.retain(|k| k != key);
+        self.recency.push_back(key.to_string());
+    }
+
+    fn evict(&mut self) {
+        while self.total_bytes > self.max_bytes {
+            let key = match self.recency.pop_front() {
+                Some(key) => key,
+                None => break,
+            };
+            if let Some(ranges) = self.ranges.remove(&key) {
+                self.total_bytes -= ranges.iter().map(|(_, _, data)| data.len()).sum::<usize>();
+            }
+        }
+    }
 }
 
 lazy_static! {
@@ -71,24 +281,79 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    /// Every format Drive can export a given Google-native mime type to, regardless of which one
+    /// `Config::export_formats` picked as the default. Backs the sibling entries presented when
+    /// `Config::export_all_formats` is set, without having to hit the Drive API for them.
+    static ref EXPORT_ALTERNATIVES: HashMap<&'static str, &'static [&'static str]> = hashmap! {
+        "application/vnd.google-apps.document" => &[
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/vnd.oasis.opendocument.text",
+            "application/pdf",
+            "text/plain",
+        ][..],
+        "application/vnd.google-apps.presentation" => &[
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "application/vnd.oasis.opendocument.presentation",
+            "application/pdf",
+        ][..],
+        "application/vnd.google-apps.spreadsheet" => &[
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "application/vnd.oasis.opendocument.spreadsheet",
+            "text/csv",
+            "application/pdf",
+        ][..],
+        "application/vnd.google-apps.drawing" => &["image/png", "image/svg+xml", "application/pdf"][..],
+        "application/vnd.google-apps.site" => &["text/plain"][..],
+    };
+}
+
 impl DriveFacade {
     /// Creates a new DriveFacade with a given config.
     pub fn new(config: &Config) -> Self {
         debug!("DriveFacade::new()");
 
-        let ttl = config.cache_max_seconds();
-        let max_count = config.cache_max_items() as usize;
-
         DriveFacade {
             hub: DriveFacade::create_drive(&config).expect("Could not create drive3::DriveHub"),
             buff: Vec::new(),
             pending_writes: HashMap::new(),
-            cache: LruCache::<String, Vec<u8>>::with_expiry_duration_and_capacity(ttl, max_count),
+            cache: RangeCache::new(config.cache_max_bytes() as usize),
+            disk_cache: DiskCache::new(
+                config.disk_cache_dir(),
+                config.disk_cache_ttl(),
+                config.disk_cache_max_bytes(),
+            ),
             root_id: None,
             changes_token: None,
+            export_formats: config.export_formats(),
+            config: config.clone(),
+            key_store: if config.encrypt_content() {
+                Some(
+                    encryption::KeyStore::load_or_create(&config.encryption_key_file())
+                        .expect("Could not load or create the content-encryption key store"),
+                )
+            } else {
+                None
+            },
+            cached_token: Mutex::new(None),
         }
     }
 
+    /// The mime type `google_mime` (a Google-native document type) would be exported as, per
+    /// `Config::export_formats`. `None` means `google_mime` isn't a recognized Google-native type.
+    pub fn export_mime_type(&self, google_mime: &str) -> Option<String> {
+        self.export_formats.get(google_mime).cloned()
+    }
+
+    /// Every mime type `google_mime` could alternatively be exported as, beyond whatever
+    /// `export_mime_type` picked as the default (see `EXPORT_ALTERNATIVES`).
+    pub fn export_alternatives(&self, google_mime: &str) -> Vec<String> {
+        EXPORT_ALTERNATIVES
+            .get(google_mime)
+            .map(|alternatives| alternatives.iter().map(|s| s.to_string()).collect())
+            .unwrap_or_default()
+    }
+
     /// Creates a Drive authenticator.
     fn create_drive_auth(config: &Config) -> Result<GcAuthenticator, Error> {
         let secret: oauth2::ConsoleApplicationSecret =
@@ -146,30 +411,37 @@ impl DriveFacade {
         &self,
         drive_id: DriveIdRef,
         mime_type: Option<String>,
+        export_mime_type: Option<String>,
     ) -> Result<usize, Error> {
-        self.get_file_content(drive_id, mime_type).map(|x| x.len())
+        self.get_file_content(drive_id, mime_type, export_mime_type)
+            .map(|x| x.len())
     }
 
     fn get_file_metadata(&self, id: DriveIdRef) -> Result<drive3::File, Error> {
         self.hub
             .files()
             .get(id)
-            .param("fields", "id,name,parents,mimeType,webContentLink")
+            .param("fields", "id,name,parents,mimeType,webContentLink,modifiedTime")
             .add_scope(drive3::Scope::Full)
             .doit()
             .map(|(_response, file)| file)
             .map_err(|e| err_msg(format!("{:#?}", e)))
     }
 
-    /// Retrieves the content of a Drive file. If `mime_type` is specified, this method will
-    /// attempt to export the file in some appropriate format rather than just download it as is.
-    /// This is the only way of retrieving Docs, Sheets, Slides, Sites and Drawings.
+    /// Retrieves the content of a Drive file. `mime_type` is this file's own Drive-native MIME
+    /// type, consulted only to detect `UNEXPORTABLE_MIME_TYPES`. `export_mime_type`, if given, is
+    /// the concrete format to export the file as rather than downloading it as-is -- the only way
+    /// of retrieving Docs, Sheets, Slides, Sites and Drawings, which have no raw bytes of their
+    /// own. Callers pick `export_mime_type` themselves (see `File::export_mime_type`) rather than
+    /// this method re-deriving Drive's default for `mime_type`, so that two `File`s for the same
+    /// Google-native document (see `Config::export_all_formats`) can each fetch their own format.
     fn get_file_content(
         &self,
         drive_id: DriveIdRef,
         mime_type: Option<String>,
+        export_mime_type: Option<String>,
     ) -> Result<Vec<u8>, Error> {
-        if let Some(mime) = mime_type.clone() {
+        if let Some(mime) = mime_type {
             if UNEXPORTABLE_MIME_TYPES.contains::<str>(&mime) {
                 return Ok(format!(
                     "UNEXPORTABLE_FILE: The MIME type of this \
@@ -186,11 +458,7 @@ impl DriveFacade {
             }
         }
 
-        let export_type: Option<&'static str> = mime_type
-            .and_then(|ref t| MIME_TYPES.get::<str>(&t))
-            .cloned();
-
-        let mut response = match export_type {
+        let mut response = match export_mime_type {
             Some(t) => {
                 let response = self
                     .hub
@@ -223,26 +491,53 @@ impl DriveFacade {
         Ok(content)
     }
 
-    /// Applies all pending writes accumulated so far on a data buffer. The pending writes are then
-    /// cleared.
-    fn apply_pending_writes_on_data(&mut self, id: DriveId, data: &mut Vec<u8>) {
-        self.pending_writes
-            .entry(id.clone())
-            .or_insert_with(Vec::new)
-            .iter()
-            .filter(|write| write.id == id)
-            .for_each(|pending_write| {
-                debug!(
-                    "Applying pending write with offset {} on {}",
-                    &pending_write.offset, &pending_write.id
-                );
-                let required_size = pending_write.offset + pending_write.data.len();
-
-                data.resize(required_size, 0);
-                data[pending_write.offset..].copy_from_slice(&pending_write.data[..]);
-            });
+    /// Streams `drive_id`'s raw content (no export, no `UNEXPORTABLE_MIME_TYPES` handling --
+    /// those only matter for Google-native documents, which `flush` never targets) straight into
+    /// `dest`, so that assembling a file's new content in `flush` never has to hold the old
+    /// content fully in memory.
+    fn stream_file_content(&self, drive_id: DriveIdRef, dest: &mut fs::File) -> Result<(), Error> {
+        let (mut response, _empty_file) = self
+            .hub
+            .files()
+            .get(&drive_id)
+            .supports_team_drives(false)
+            .param("alt", "media")
+            .add_scope(drive3::Scope::Full)
+            .doit()
+            .map_err(|e| err_msg(format!("{:#?}", e)))?;
 
-        self.pending_writes.remove(&id);
+        io::copy(&mut response, dest)?;
+        Ok(())
+    }
+
+    /// Applies `writes` directly onto a scratch file, seeking to each write's offset instead of
+    /// growing an in-memory buffer. `truncate` represents itself as a pending write with empty
+    /// data, which is applied here as a `set_len` rather than a no-op write, so that it actually
+    /// shrinks the file instead of leaving the downloaded content behind it untouched. Takes
+    /// `writes` by reference rather than draining `self.pending_writes` itself, so `flush` can
+    /// reapply the same batch across retries (see `MAX_FLUSH_ATTEMPTS`) instead of losing it after
+    /// the first attempt.
+    fn apply_pending_writes_on_file(
+        writes: &[PendingWrite],
+        id: &DriveId,
+        file: &mut fs::File,
+    ) -> Result<(), Error> {
+        for pending_write in writes.iter().filter(|write| &write.id == id) {
+            debug!(
+                "Applying pending write with offset {} on {}",
+                pending_write.offset, pending_write.id
+            );
+
+            if pending_write.data.is_empty() {
+                file.set_len(pending_write.offset as u64)?;
+                continue;
+            }
+
+            file.seek(SeekFrom::Start(pending_write.offset as u64))?;
+            file.write_all(&pending_write.data)?;
+        }
+
+        Ok(())
     }
 
     /// Returns the Drive ID of the root "My Drive" directory. Caches the value.
@@ -311,8 +606,25 @@ impl DriveFacade {
         Ok(self.changes_token.as_ref().unwrap())
     }
 
+    /// The last `changes.list` page token seen, if any. Used to persist progress across mounts
+    /// (see `FileManager::save_snapshot`) instead of restarting from `get_start_page_token()`.
+    pub fn persisted_changes_token(&self) -> Option<String> {
+        self.changes_token.clone()
+    }
+
+    /// Restores a `changes.list` page token previously returned by `persisted_changes_token`, so
+    /// that the next `get_all_changes()` resumes from where the last mount left off.
+    pub fn restore_changes_token(&mut self, token: Option<String>) {
+        self.changes_token = token;
+    }
+
     /// Returns a list of all changes reported by Drive which are more recent than the changes
     /// token indicates.
+    ///
+    /// Unlike `get_all_files`, this isn't sharded by `modifiedTime`: `changes.list` has no `q`
+    /// parameter to filter by, and each page's token is only handed back by the previous page, so
+    /// the pages are inherently sequential rather than independently fetchable windows. It stays
+    /// a single serial loop; `Config::list_parallelism` only applies to `get_all_files`.
     pub fn get_all_changes(&mut self) -> Result<Vec<drive3::Change>, Error> {
         let mut all_changes = Vec::new();
 
@@ -348,62 +660,109 @@ impl DriveFacade {
         Ok(all_changes)
     }
 
-    /// Returns a list of all files from Drive. If the `parents` list is provided, only files which are children of any one of the list's elements are returned. If `trashed` is provided, only files which are trashed/not trashed are returned. The two filters can be used together.
+    /// Returns a list of all files from Drive. If the `parents` list is provided, only files which
+    /// are children of any one of the list's elements are returned. If `trashed` is provided, only
+    /// files which are trashed/not trashed are returned. The two filters can be used together.
+    ///
+    /// The listing is sharded across `Config::list_parallelism` adjacent `modifiedTime` windows
+    /// (see `time_windows`), each paged serially but on its own connection, so a Drive with
+    /// hundreds of thousands of files doesn't have to wait on one request at a time.
     pub fn get_all_files(
         &mut self,
         parents: Option<Vec<DriveId>>,
         trashed: Option<bool>,
+    ) -> Result<Vec<drive3::File>, Error> {
+        let mut query_chain: Vec<String> = Vec::new();
+        if let Some(ref p) = parents {
+            let q = p
+                .iter()
+                .map(|id| format!("'{}' in parents", id))
+                .collect::<Vec<_>>()
+                .join(" or ");
+
+            query_chain.push(format!("({})", q));
+        }
+        if let Some(trash) = trashed {
+            query_chain.push(format!("trashed = {}", trash));
+        }
+        let base_query = query_chain.join(" and ");
+
+        let windows = self.time_windows(&base_query, self.config.list_parallelism())?;
+        let config = self.config.clone();
+
+        let handles: Vec<_> = windows
+            .into_iter()
+            .enumerate()
+            .map(|(shard, query)| {
+                let config = config.clone();
+                thread::spawn(move || -> Result<Vec<drive3::File>, Error> {
+                    let hub = Self::create_drive(&config)?;
+                    Self::fetch_all_files_pages(&hub, &query, shard)
+                })
+            })
+            .collect();
+
+        // A file can appear in only one window's query, but boundary races (a file's
+        // `modifiedTime` changing concurrently with the scan) could in principle land it in two,
+        // so de-duplicate by id rather than trusting the windows to be disjoint at fetch time.
+        let mut seen_ids: HashSet<String> = HashSet::new();
+        let mut all_files = Vec::new();
+        for handle in handles {
+            let files = handle
+                .join()
+                .map_err(|_| err_msg("A file-listing shard thread panicked"))??;
+            for file in files {
+                if let Some(ref id) = file.id {
+                    if !seen_ids.insert(id.clone()) {
+                        continue;
+                    }
+                }
+                all_files.push(file);
+            }
+        }
+
+        Ok(all_files)
+    }
+
+    /// Pages through every file matching `query`, following `nextPageToken` until exhausted.
+    /// `shard` is only used to label log lines, so concurrent shards (see `get_all_files`) don't
+    /// get confused for one another.
+    fn fetch_all_files_pages(
+        hub: &GcDriveHub,
+        query: &str,
+        shard: usize,
     ) -> Result<Vec<drive3::File>, Error> {
         let mut all_files = Vec::new();
         let mut page_token: Option<String> = None;
         let mut current_page = 1;
         loop {
-            let mut request = self.hub.files()
+            let mut request = hub.files()
                 .list()
                 .param("fields", "nextPageToken,files(name,id,size,mimeType,owners,parents,trashed,modifiedTime,createdTime,viewedByMeTime)")
                 .spaces("drive") // TODO: maybe add photos as well
                 .corpora("user")
                 .page_size(PAGE_SIZE)
-                .add_scope(drive3::Scope::Full);
+                .add_scope(drive3::Scope::Full)
+                .q(query);
 
             if let Some(token) = page_token {
                 request = request.page_token(&token);
             };
 
-            let mut query_chain: Vec<String> = Vec::new();
-            if let Some(ref p) = parents {
-                let q = p
-                    .iter()
-                    .map(|id| format!("'{}' in parents", id))
-                    .collect::<Vec<_>>()
-                    .join(" or ");
-
-                query_chain.push(format!("({})", q));
-            }
-            if let Some(trash) = trashed {
-                query_chain.push(format!("trashed = {}", trash));
-            }
-
-            // TODO: shard files by time and batch requests that way.
-            // query_chain.push(String::from("modifiedTime > '2021-01-01T00:00:00'"));
-
-            let query = query_chain.join(" and ");
-            let (_, filelist) = request
-                .q(&query)
-                .doit()
-                .map_err(|e| err_msg(format!("{:#?}", e)))?;
+            let (_, filelist) = request.doit().map_err(|e| err_msg(format!("{:#?}", e)))?;
 
             match filelist.files {
                 Some(files) => {
                     info!(
-                        "Received page {} containing {} files (requested {})",
+                        "Shard {}: received page {} containing {} files (requested {})",
+                        shard,
                         current_page,
                         files.len(),
                         PAGE_SIZE,
                     );
                     all_files.extend(files);
                 }
-                _ => warn!("Filelist does not contain any files!"),
+                _ => warn!("Shard {}: filelist does not contain any files!", shard),
             };
 
             current_page += 1;
@@ -415,26 +774,287 @@ impl DriveFacade {
         Ok(all_files)
     }
 
-    /// Reads the contents of a Drive file starting at a certain offset.
-    /// Prefers reading from cache if possible, otherwise fetches the content from Drive.
+    /// Splits `base_query`'s matching files into `shards` adjacent `modifiedTime` windows of
+    /// roughly equal time span, by probing the oldest and newest `modifiedTime` among them and
+    /// bisecting the range. Falls back to a single unsharded window (`base_query` itself) if
+    /// `shards <= 1` or there's nothing to bisect (an empty or single-file result).
+    fn time_windows(&mut self, base_query: &str, shards: u32) -> Result<Vec<String>, Error> {
+        if shards <= 1 {
+            return Ok(vec![base_query.to_string()]);
+        }
+
+        let (oldest, newest) = match (
+            self.probe_modified_time(base_query, true)?,
+            self.probe_modified_time(base_query, false)?,
+        ) {
+            (Some(oldest), Some(newest)) => (OffsetDateTime::from(oldest), OffsetDateTime::from(newest)),
+            _ => return Ok(vec![base_query.to_string()]),
+        };
+
+        let span = newest - oldest;
+        if span <= time::Duration::ZERO {
+            return Ok(vec![base_query.to_string()]);
+        }
+
+        let step = span / shards as i32;
+        let mut windows = Vec::with_capacity(shards as usize);
+        for i in 0..shards {
+            let start = oldest + step * i as i32;
+            let end = if i + 1 == shards {
+                // Nudge the last window's upper bound past `newest` so a file sitting exactly on
+                // it isn't dropped by the exclusive `<`.
+                newest + time::Duration::SECOND
+            } else {
+                oldest + step * (i as i32 + 1)
+            };
+
+            let time_filter = format!(
+                "modifiedTime >= '{}' and modifiedTime < '{}'",
+                Self::format_rfc3339(start),
+                Self::format_rfc3339(end)
+            );
+            windows.push(if base_query.is_empty() {
+                time_filter
+            } else {
+                format!("{} and {}", base_query, time_filter)
+            });
+        }
+        Ok(windows)
+    }
+
+    /// Fetches just the oldest (`ascending`) or newest (`!ascending`) `modifiedTime` among files
+    /// matching `base_query`, or `None` if no file matches it at all.
+    fn probe_modified_time(
+        &mut self,
+        base_query: &str,
+        ascending: bool,
+    ) -> Result<Option<std::time::SystemTime>, Error> {
+        let mut request = self
+            .hub
+            .files()
+            .list()
+            .param("fields", "files(modifiedTime)")
+            .spaces("drive")
+            .corpora("user")
+            .page_size(1)
+            .order_by(if ascending { "modifiedTime" } else { "modifiedTime desc" })
+            .add_scope(drive3::Scope::Full);
+
+        if !base_query.is_empty() {
+            request = request.q(base_query);
+        }
+
+        let (_, filelist) = request.doit().map_err(|e| err_msg(format!("{:#?}", e)))?;
+
+        Ok(filelist
+            .files
+            .and_then(|files| files.into_iter().next())
+            .map(|file| file.modified_time.unwrap_or_default().into()))
+    }
+
+    /// Formats `t` the way Drive's `q` filter expects timestamps: RFC 3339.
+    fn format_rfc3339(t: OffsetDateTime) -> String {
+        t.format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_default()
+    }
+
+    /// The key `read`'s cache is stored under: just `drive_id` for a file downloaded as-is, or
+    /// `drive_id` plus the export format when one was requested, so that two sibling `File`s for
+    /// the same Google-native document (see `Config::export_all_formats`) don't clobber each
+    /// other's cached content.
+    fn content_cache_key(drive_id: &str, export_mime_type: Option<&str>) -> String {
+        match export_mime_type {
+            Some(export) => format!("{}:{}", drive_id, export),
+            None => drive_id.to_string(),
+        }
+    }
+
+    /// The installed app's `client_id`/`client_secret`, parsed out of `Config::client_secret` the
+    /// same way `create_drive_auth` does. Only meaningful for the interactive OAuth flow; a
+    /// service account has no `client_id`/`client_secret` pair to refresh with (see
+    /// `auth::refresh_service_account_token` instead).
+    fn installed_app_credentials(config: &Config) -> Result<(String, String), Error> {
+        let secret: oauth2::ConsoleApplicationSecret = serde_json::from_str(config.client_secret())?;
+        let secret = secret
+            .installed
+            .ok_or_else(|| err_msg("ConsoleApplicationSecret.installed is None"))?;
+
+        Ok((secret.client_id, secret.client_secret))
+    }
+
+    /// A bearer token good for the `drive3::Scope::Full` scope, used by the raw `hyper` request in
+    /// `get_file_range` that the generated Drive client's builders don't expose (a ranged GET).
+    /// Served from `cached_token` whenever it's still fresh; otherwise refreshes it first (see
+    /// `refresh_cached_token`), so callers within the same token's lifetime share one token
+    /// instead of each re-reading the token file or racing to refresh it.
+    fn access_token(&self) -> Result<String, Error> {
+        {
+            let cached = self.cached_token.lock().unwrap();
+            if let Some(token) = cached.as_ref() {
+                if OffsetDateTime::now_utc() + TOKEN_REFRESH_MARGIN < token.expires_at {
+                    return Ok(token.access_token.clone());
+                }
+            }
+        }
+
+        self.refresh_cached_token()
+    }
+
+    /// Refreshes `cached_token` if it's missing or within `TOKEN_REFRESH_MARGIN` of expiry,
+    /// performing a `refresh_token` grant (or, for a service account, minting a fresh JWT-signed
+    /// token) against `Config::token_store` and writing the result back there, then returns
+    /// whatever access token ends up cached. Holds `cached_token`'s lock for the whole check,
+    /// so two callers racing on an expired token only refresh it once.
+    fn refresh_cached_token(&self) -> Result<String, Error> {
+        let mut cached = self.cached_token.lock().unwrap();
+
+        if let Some(token) = cached.as_ref() {
+            if OffsetDateTime::now_utc() + TOKEN_REFRESH_MARGIN < token.expires_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let store = self.config.token_store();
+        let needs_refresh = match auth::cached_access_token(&store) {
+            Ok((_, expires_at)) => OffsetDateTime::now_utc() + TOKEN_REFRESH_MARGIN >= expires_at,
+            Err(_) => true,
+        };
+
+        if needs_refresh {
+            if self.config.use_service_account() {
+                auth::refresh_service_account_token(self.config.service_account_key(), &store)?;
+            } else {
+                let (client_id, client_secret) = Self::installed_app_credentials(&self.config)?;
+                auth::refresh_access_token(&client_id, &client_secret, &store)?;
+            }
+        }
+
+        let (access_token, expires_at) = auth::cached_access_token(&store)?;
+        *cached = Some(CachedToken {
+            access_token: access_token.clone(),
+            expires_at,
+        });
+
+        Ok(access_token)
+    }
+
+    /// Fetches `[offset, offset+size)` of `drive_id`'s content via a `Range` request against the
+    /// `alt=media` download endpoint, falling back to the `UNEXPORTABLE_MIME_TYPES` placeholder
+    /// `get_file_content` would have returned. The caller still has to check `RangeResponse::partial`:
+    /// some Drive responses ignore `Range` entirely and send back the whole file as `200 OK`.
+    fn get_file_range(
+        &self,
+        drive_id: DriveIdRef,
+        mime_type: Option<String>,
+        offset: usize,
+        size: usize,
+    ) -> Result<RangeResponse, Error> {
+        if let Some(mime) = mime_type {
+            if UNEXPORTABLE_MIME_TYPES.contains::<str>(&mime) {
+                return Ok(RangeResponse {
+                    data: format!(
+                        "UNEXPORTABLE_FILE: The MIME type of this \
+                         file is {:?}, which can not be exported from Drive. Web \
+                         content link provided by Drive: {:?}\n",
+                        mime,
+                        self.get_file_metadata(drive_id)
+                            .ok()
+                            .map(|metadata| metadata.web_view_link)
+                            .unwrap_or_default()
+                    )
+                    .into_bytes(),
+                    partial: false,
+                });
+            }
+        }
+
+        let range_end = offset + size - 1;
+        let client = hyper::Client::with_connector(hyper::net::HttpsConnector::new(
+            hyper_rustls::TlsClient::new(),
+        ));
+        let url = format!(
+            "https://www.googleapis.com/drive/v3/files/{}?alt=media&supportsTeamDrives=false",
+            drive_id
+        );
+
+        let mut response = client
+            .get(&url)
+            .header(hyper::header::Authorization(hyper::header::Bearer {
+                token: self.access_token()?,
+            }))
+            .header(hyper::header::Range::bytes(
+                offset as u64,
+                range_end as u64,
+            ))
+            .send()
+            .map_err(|e| err_msg(format!("{:#?}", e)))?;
+
+        let mut data = Vec::new();
+        response.read_to_end(&mut data)?;
+
+        Ok(RangeResponse {
+            partial: response.status == hyper::status::StatusCode::PartialContent,
+            data,
+        })
+    }
+
+    /// Reads the contents of a Drive file starting at a certain offset. Prefers reading from
+    /// cache if possible, otherwise fetches just `[offset, offset+size)` from Drive via a `Range`
+    /// request. Google-native document exports (`export_mime_type.is_some()`) don't honor `Range`
+    /// at all, so those still fall back to downloading (and caching) the whole file; they're also
+    /// never encrypted, since their bytes are generated server-side. Transparent decryption (see
+    /// `read_encrypted`) is otherwise applied whenever `key_store` is set.
     pub fn read(
         &mut self,
         drive_id: DriveIdRef,
         mime_type: Option<String>,
+        export_mime_type: Option<String>,
         offset: usize,
         size: usize,
     ) -> Option<&[u8]> {
-        if let Some(data) = self.cache.get(drive_id) {
-            self.buff =
-                data[cmp::min(data.len(), offset)..cmp::min(data.len(), offset + size)].to_vec();
-            return Some(&self.buff);
+        let cache_key = Self::content_cache_key(drive_id, export_mime_type.as_deref());
+
+        if export_mime_type.is_some() {
+            if let Some(data) = self.cache.get(&cache_key, offset, size) {
+                self.buff = data;
+                return Some(&self.buff);
+            }
+
+            if let Some(data) = self.disk_cache.get(&cache_key) {
+                let start = cmp::min(data.len(), offset);
+                let end = cmp::min(data.len(), offset + size);
+                self.buff = data[start..end].to_vec();
+                self.cache.insert(&cache_key, 0, data);
+                return Some(&self.buff);
+            }
+
+            return match self.get_file_content(&drive_id, mime_type, export_mime_type) {
+                Ok(data) => {
+                    let start = cmp::min(data.len(), offset);
+                    let end = cmp::min(data.len(), offset + size);
+                    self.buff = data[start..end].to_vec();
+                    if let Err(e) = self.disk_cache.insert(&cache_key, &data) {
+                        warn!("failed to persist {} to the disk cache: {:?}", cache_key, e);
+                    }
+                    self.cache.insert(&cache_key, 0, data);
+                    Some(&self.buff)
+                }
+                Err(e) => {
+                    error!("Got error: {:?}", e);
+                    None
+                }
+            };
         }
 
-        match self.get_file_content(&drive_id, mime_type) {
+        let result = if self.key_store.is_some() {
+            self.read_encrypted(drive_id, mime_type, &cache_key, offset, size)
+        } else {
+            self.read_plain(drive_id, mime_type, &cache_key, offset, size)
+        };
+
+        match result {
             Ok(data) => {
-                self.buff = data[cmp::min(data.len(), offset)..cmp::min(data.len(), offset + size)]
-                    .to_vec();
-                self.cache.insert(drive_id.to_string(), data.to_vec());
+                self.buff = data;
                 Some(&self.buff)
             }
             Err(e) => {
@@ -444,6 +1064,129 @@ impl DriveFacade {
         }
     }
 
+    /// The non-encrypted read path: cache keys and file offsets are one and the same as what the
+    /// caller asked for. Shared between files for which encryption is disabled entirely and files
+    /// that predate encryption being turned on (`read_encrypted` falls back to this once it sees
+    /// the content doesn't start with `encryption::MAGIC`).
+    fn read_plain(
+        &mut self,
+        drive_id: DriveIdRef,
+        mime_type: Option<String>,
+        cache_key: &str,
+        offset: usize,
+        size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        if let Some(data) = self.cache.get(cache_key, offset, size) {
+            return Ok(data);
+        }
+
+        if let Some(data) = self.disk_cache.get(cache_key) {
+            let start = cmp::min(data.len(), offset);
+            let end = cmp::min(data.len(), offset + size);
+            let result = data[start..end].to_vec();
+            self.cache.insert(cache_key, 0, data);
+            return Ok(result);
+        }
+
+        let range = self.get_file_range(drive_id, mime_type, offset, size)?;
+        if range.partial {
+            self.cache.insert(cache_key, offset, range.data.clone());
+            Ok(range.data)
+        } else {
+            // The server ignored Range and handed back the whole file; since we already have it
+            // all, cache it as such (both in memory and on disk) instead of re-requesting
+            // piecemeal later.
+            debug!(
+                "server returned the whole file for {} instead of honoring Range",
+                drive_id
+            );
+            let start = cmp::min(range.data.len(), offset);
+            let end = cmp::min(range.data.len(), offset + size);
+            let result = range.data[start..end].to_vec();
+            if let Err(e) = self.disk_cache.insert(cache_key, &range.data) {
+                warn!("failed to persist {} to the disk cache: {:?}", cache_key, e);
+            }
+            self.cache.insert(cache_key, 0, range.data);
+            Ok(result)
+        }
+    }
+
+    /// The encrypted read path. `cache_key` still caches raw Drive bytes exactly like
+    /// `read_plain` does (the cache is oblivious to encryption) -- only here those raw bytes
+    /// happen to be ciphertext, and file offsets are ciphertext offsets rather than plaintext
+    /// ones. Fetches and decrypts only the fixed-size blocks (see `encryption::plan_range`) that
+    /// overlap `[offset, offset+size)` in plaintext space, so a small read against a huge
+    /// encrypted file still stays cheap.
+    fn read_encrypted(
+        &mut self,
+        drive_id: DriveIdRef,
+        mime_type: Option<String>,
+        cache_key: &str,
+        offset: usize,
+        size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let key_store = self
+            .key_store
+            .clone()
+            .expect("read_encrypted() called without a key store");
+
+        let base_nonce = match self.encryption_header(drive_id, mime_type.clone(), cache_key)? {
+            Some(base_nonce) => base_nonce,
+            // No header magic: this file predates encryption being enabled. Read it as plaintext.
+            None => return self.read_plain(drive_id, mime_type, cache_key, offset, size),
+        };
+
+        let plan = encryption::plan_range(offset, size);
+        let ciphertext_offset = encryption::HEADER_LEN + plan.block_start;
+
+        let ciphertext = if let Some(data) = self
+            .cache
+            .get(cache_key, ciphertext_offset, plan.ciphertext_len)
+        {
+            data
+        } else {
+            let range = self.get_file_range(drive_id, mime_type, ciphertext_offset, plan.ciphertext_len)?;
+            if range.partial {
+                self.cache
+                    .insert(cache_key, ciphertext_offset, range.data.clone());
+                range.data
+            } else {
+                self.cache.insert(cache_key, 0, range.data.clone());
+                let start = cmp::min(range.data.len(), ciphertext_offset);
+                let end = cmp::min(range.data.len(), ciphertext_offset + plan.ciphertext_len);
+                range.data[start..end].to_vec()
+            }
+        };
+
+        let plaintext_blocks =
+            encryption::decrypt_blocks(&key_store, &base_nonce, plan.first_block_index, &ciphertext)?;
+
+        let start = cmp::min(plaintext_blocks.len(), plan.skip_prefix);
+        let end = cmp::min(plaintext_blocks.len(), plan.skip_prefix + size);
+        Ok(plaintext_blocks[start..end].to_vec())
+    }
+
+    /// Fetches (and caches) `drive_id`'s encryption header -- the first `encryption::HEADER_LEN`
+    /// bytes of its content -- and returns the base nonce it encodes, or `None` if those bytes
+    /// don't start with `encryption::MAGIC` (a file written before encryption was turned on).
+    fn encryption_header(
+        &mut self,
+        drive_id: DriveIdRef,
+        mime_type: Option<String>,
+        cache_key: &str,
+    ) -> Result<Option<[u8; encryption::BASE_NONCE_LEN]>, Error> {
+        let header_bytes = match self.cache.get(cache_key, 0, encryption::HEADER_LEN) {
+            Some(data) => data,
+            None => {
+                let range = self.get_file_range(drive_id, mime_type, 0, encryption::HEADER_LEN)?;
+                self.cache.insert(cache_key, 0, range.data.clone());
+                range.data
+            }
+        };
+
+        Ok(encryption::parse_header(&header_bytes))
+    }
+
     /// Creates a new file on Drive. If successful, returns the file id.
     pub fn create(&mut self, drive_file: &drive3::File) -> Result<DriveId, Error> {
         let dummy_file = DummyFile::new(&[]);
@@ -478,6 +1221,22 @@ impl DriveFacade {
             .push(pending_write);
     }
 
+    /// Discards any cached/pending content for `id` and queues a write that truncates it to zero
+    /// bytes. Used by `Gcsf::open` to honor `O_TRUNC`; the next `flush()` overwrites the file
+    /// with an empty body plus whatever gets written afterwards.
+    pub fn truncate(&mut self, id: DriveId) {
+        self.cache.remove(&id);
+        self.disk_cache.remove(&id);
+        self.pending_writes.insert(
+            id.clone(),
+            vec![PendingWrite {
+                id,
+                offset: 0,
+                data: Vec::new(),
+            }],
+        );
+    }
+
     /// Deletes a file permanently from Drive.
     pub fn delete_permanently(&mut self, id: DriveIdRef) -> Result<bool, Error> {
         self.hub
@@ -533,13 +1292,178 @@ impl DriveFacade {
             .map_err(|e| err_msg(format!("DriveFacade::move_to_trash() {}", e)))
     }
 
-    /// Applies pending write operations. Similar to flushing a stream.
+    /// Un-marks a Google Drive file as trashed. Inverse of `move_to_trash`.
+    pub fn restore_from_trash(&mut self, id: DriveId) -> Result<(), Error> {
+        let f = drive3::File {
+            trashed: Some(false),
+            ..Default::default()
+        };
+
+        self.hub
+            .files()
+            .update(f, &id)
+            .add_scope(drive3::Scope::Full)
+            .doit_without_upload()
+            .map(|_| ())
+            .map_err(|e| err_msg(format!("DriveFacade::restore_from_trash() {}", e)))
+    }
+
+    /// Stars or un-stars a Google Drive file.
+    pub fn set_starred(&mut self, id: DriveId, starred: bool) -> Result<(), Error> {
+        let f = drive3::File {
+            starred: Some(starred),
+            ..Default::default()
+        };
+
+        self.hub
+            .files()
+            .update(f, &id)
+            .add_scope(drive3::Scope::Full)
+            .doit_without_upload()
+            .map(|_| ())
+            .map_err(|e| err_msg(format!("DriveFacade::set_starred() {}", e)))
+    }
+
+    /// Lists every permission currently granted on `id`'s Drive file.
+    pub fn list_permissions(&self, id: DriveIdRef) -> Result<Vec<Permission>, Error> {
+        let (_response, list) = self
+            .hub
+            .permissions()
+            .list(id)
+            .param("fields", "permissions(id,type,role,emailAddress,domain)")
+            .supports_team_drives(false)
+            .add_scope(drive3::Scope::Full)
+            .doit()
+            .map_err(|e| err_msg(format!("DriveFacade::list_permissions() {}", e)))?;
+
+        Ok(list
+            .permissions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|perm| Permission {
+                id: perm.id,
+                role: perm.role.unwrap_or_default(),
+                type_: perm.type_.unwrap_or_default(),
+                email_address: perm.email_address,
+                domain: perm.domain,
+            })
+            .collect())
+    }
+
+    /// Grants `role` to `type_` on `id`'s Drive file, where `email` is the grantee's address (used
+    /// for `type_` `user`/`group`, ignored for `domain`/`anyone`) and `domain` is the grantee's
+    /// domain (used for `type_` `domain` only). Lists existing permissions first and skips
+    /// creation if an equivalent grant is already present, so reconciling the same desired state
+    /// twice is a no-op rather than piling up duplicate permissions.
+    pub fn add_permission_if_not_exists(
+        &mut self,
+        id: DriveIdRef,
+        email: Option<&str>,
+        domain: Option<&str>,
+        role: &str,
+        type_: &str,
+    ) -> Result<(), Error> {
+        let already_granted = self.list_permissions(id)?.into_iter().any(|perm| {
+            perm.role == role
+                && perm.type_ == type_
+                && perm.email_address.as_deref() == email
+                && perm.domain.as_deref() == domain
+        });
+
+        if already_granted {
+            debug!(
+                "{} is already shared as {}:{}:{:?}{:?}, nothing to do",
+                id, role, type_, email, domain
+            );
+            return Ok(());
+        }
+
+        let permission = drive3::Permission {
+            role: Some(role.to_string()),
+            type_: Some(type_.to_string()),
+            email_address: email.map(String::from),
+            domain: domain.map(String::from),
+            ..Default::default()
+        };
+
+        self.hub
+            .permissions()
+            .create(permission, id)
+            .supports_team_drives(false)
+            .send_notification_email(false)
+            .add_scope(drive3::Scope::Full)
+            .doit()
+            .map(|_| ())
+            .map_err(|e| err_msg(format!("DriveFacade::add_permission_if_not_exists() {}", e)))
+    }
+
+    /// Revokes a previously granted permission from `id`'s Drive file.
+    pub fn remove_permission(&mut self, id: DriveIdRef, permission_id: &str) -> Result<(), Error> {
+        self.hub
+            .permissions()
+            .delete(id, permission_id)
+            .supports_team_drives(false)
+            .add_scope(drive3::Scope::Full)
+            .doit()
+            .map(|_| ())
+            .map_err(|e| err_msg(format!("DriveFacade::remove_permission() {}", e)))
+    }
+
+    /// Reconciles `id`'s sharing state to exactly `desired`: grants whichever entries in `desired`
+    /// aren't already present (ignoring their `id`, since the caller building a desired state has
+    /// no permission id to give it) and revokes whichever currently-granted permissions aren't in
+    /// `desired`. Backs writing the full `user.gcsf.share` xattr.
+    pub fn reconcile_permissions(&mut self, id: DriveIdRef, desired: &[Permission]) -> Result<(), Error> {
+        let current = self.list_permissions(id)?;
+
+        for perm in &current {
+            let still_wanted = desired.iter().any(|d| {
+                d.role == perm.role
+                    && d.type_ == perm.type_
+                    && d.email_address == perm.email_address
+                    && d.domain == perm.domain
+            });
+
+            if !still_wanted {
+                if let Some(ref permission_id) = perm.id {
+                    self.remove_permission(id, permission_id)?;
+                }
+            }
+        }
+
+        for perm in desired {
+            self.add_permission_if_not_exists(
+                id,
+                perm.email_address.as_deref(),
+                perm.domain.as_deref(),
+                &perm.role,
+                &perm.type_,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies pending write operations. Similar to flushing a stream. Assembles the file's new
+    /// content in a scratch file on disk (the old remote content streamed in, pending writes
+    /// seeked/written on top) rather than in a `Vec<u8>`, so a multi-gigabyte file only ever needs
+    /// a small buffer resident at any one time, both while it's being rebuilt and while it's
+    /// re-uploaded.
+    ///
+    /// Since this is a read-modify-write, it's guarded against the remote file changing underneath
+    /// it (another gcsf mount, or a human editing it via Drive directly) between the download and
+    /// the upload: each attempt captures `modifiedTime` before downloading and checks it again
+    /// right before calling `update_file_content`, skipping the upload if it no longer matches (see
+    /// `FlushConflict`). On a conflict, it re-downloads, reapplies the same pending writes, and
+    /// tries again with backoff, up to `MAX_FLUSH_ATTEMPTS` attempts in all.
     pub fn flush(&mut self, id: DriveIdRef) -> Result<(), Error> {
         if !self.pending_writes.contains_key(id) {
             debug!("flush({}): no pending writes", id);
             return Ok(());
         }
+
         self.cache.remove(id);
+        self.disk_cache.remove(id);
 
         if let Ok(false) = self.contains(id) {
             return Err(err_msg(format!(
@@ -548,39 +1472,262 @@ impl DriveFacade {
             )));
         }
 
-        let mut file_data = self.get_file_content(&id, None).unwrap_or_default();
-        self.apply_pending_writes_on_data(DriveId::from(id), &mut file_data);
-        self.update_file_content(DriveId::from(id), &file_data)?;
+        fs::create_dir_all(UPLOAD_SCRATCH_DIR)?;
+        let scratch_path = Self::scratch_path(id);
+        let encrypted_scratch_path = Self::encrypted_scratch_path(id);
+        let drive_id = DriveId::from(id);
+        let writes = self.pending_writes.remove(id).unwrap_or_default();
 
-        Ok(())
+        let result = (|| -> Result<(), Error> {
+            for attempt in 1..=MAX_FLUSH_ATTEMPTS {
+                let expected_modified_time = self.get_file_metadata(id)?.modified_time;
+
+                {
+                    let mut scratch = fs::File::create(&scratch_path)?;
+                    self.stream_file_content(id, &mut scratch)?;
+                    Self::apply_pending_writes_on_file(&writes, &drive_id, &mut scratch)?;
+                }
+
+                // Sniffed from the plaintext, since that's what a client opening the file actually
+                // sees; ciphertext has no recognizable magic of its own.
+                let mime_guess = Self::sniff_mime_type(&scratch_path)?;
+
+                let upload_path = match self.key_store {
+                    Some(ref key_store) => {
+                        let mut src = fs::File::open(&scratch_path)?;
+                        let mut dest = fs::File::create(&encrypted_scratch_path)?;
+                        encryption::encrypt_file(key_store, &mut src, &mut dest)?;
+                        &encrypted_scratch_path
+                    }
+                    None => &scratch_path,
+                };
+
+                // Best-effort guard against the remote file having changed since we downloaded it
+                // above: Drive has no true conditional-write primitive, so this is a check
+                // immediately before the upload rather than an atomic compare-and-swap, but it
+                // still catches the common case of something else touching the file while we
+                // were rebuilding it.
+                let current_modified_time = self.get_file_metadata(id)?.modified_time;
+                let conflict = current_modified_time != expected_modified_time;
+
+                let upload_result = if conflict {
+                    Err(FlushConflict {
+                        id: drive_id.clone(),
+                    }
+                    .into())
+                } else {
+                    self.update_file_content(drive_id.clone(), upload_path, &mime_guess)
+                };
+
+                match upload_result {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        if e.downcast_ref::<FlushConflict>().is_none() || attempt == MAX_FLUSH_ATTEMPTS {
+                            return Err(e);
+                        }
+
+                        let backoff = Duration::from_millis(200 * 2u64.pow(attempt - 1));
+                        warn!(
+                            "flush({}): {} (attempt {}/{}), retrying in {:?}",
+                            id, e, attempt, MAX_FLUSH_ATTEMPTS, backoff
+                        );
+                        thread::sleep(backoff);
+                    }
+                }
+            }
+
+            Err(err_msg(format!(
+                "flush({}): exhausted all {} attempts",
+                id, MAX_FLUSH_ATTEMPTS
+            )))
+        })();
+
+        let _ = fs::remove_file(&scratch_path);
+        let _ = fs::remove_file(&encrypted_scratch_path);
+        result
     }
 
-    /// Updates the content of a file on Drive. The MIME type is guessed appropriately based on the
-    /// content.
-    fn update_file_content(
-        &mut self,
-        id: DriveId,
-        data: &[u8],
-    ) -> Result<(Response, drive3::File), Error> {
-        let mime_guess = data.sniff_mime_type().unwrap_or("application/octet-stream");
+    /// Where `flush` assembles `id`'s new (plaintext) content before uploading it.
+    fn scratch_path(id: DriveIdRef) -> PathBuf {
+        Path::new(UPLOAD_SCRATCH_DIR).join(id)
+    }
+
+    /// Where `flush` encrypts the scratch file into before uploading it, when `key_store` is set.
+    fn encrypted_scratch_path(id: DriveIdRef) -> PathBuf {
+        Path::new(UPLOAD_SCRATCH_DIR).join(format!("{}.enc", id))
+    }
+
+    /// Guesses a file's MIME type from a small prefix of its content.
+    fn sniff_mime_type(path: &Path) -> Result<String, Error> {
+        let prefix_len = cmp::min(4096, fs::metadata(path)?.len() as usize);
+        let mut prefix = vec![0u8; prefix_len];
+        fs::File::open(path)?.read_exact(&mut prefix)?;
+
+        Ok(prefix
+            .sniff_mime_type()
+            .unwrap_or("application/octet-stream")
+            .to_string())
+    }
+
+    /// Updates the content of a file on Drive, streaming it from `path` via a resumable upload
+    /// session instead of reading it fully into memory first.
+    fn update_file_content(&mut self, id: DriveId, path: &Path, mime_type: &str) -> Result<(), Error> {
         debug!(
             "Updating file content for {}. Mime type guess based on content: {}",
-            &id, &mime_guess
+            &id, mime_type
         );
 
-        let file = drive3::File {
-            mime_type: Some(mime_guess.to_string()),
-            ..Default::default()
+        self.upload_resumable_streamed(&id, path, mime_type)
+    }
+
+    /// Starts a new resumable upload session that overwrites `file_id`'s content in place,
+    /// returning the session URI Drive hands back in the `Location` header. Subsequent chunks are
+    /// PUT to that URI (see `upload_chunk`).
+    fn start_resumable_session(&self, file_id: &str, mime_type: &str) -> Result<String, Error> {
+        let client = hyper::Client::with_connector(hyper::net::HttpsConnector::new(
+            hyper_rustls::TlsClient::new(),
+        ));
+
+        let url = format!(
+            "https://www.googleapis.com/upload/drive/v3/files/{}?uploadType=resumable&supportsTeamDrives=false",
+            file_id
+        );
+
+        let mut headers = hyper::header::Headers::new();
+        headers.set(hyper::header::Authorization(hyper::header::Bearer {
+            token: self.access_token()?,
+        }));
+        headers.set(hyper::header::ContentLength(0));
+        headers.set_raw("X-Upload-Content-Type", vec![mime_type.as_bytes().to_vec()]);
+
+        let response = client
+            .request(hyper::method::Method::Patch, &url)
+            .headers(headers)
+            .send()
+            .map_err(|e| err_msg(format!("{:#?}", e)))?;
+
+        response
+            .headers
+            .get::<hyper::header::Location>()
+            .map(|location| location.0.clone())
+            .ok_or_else(|| err_msg("Drive did not return a resumable session URI"))
+    }
+
+    /// PUTs one chunk of a resumable upload. `total` is the full upload size; Drive replies
+    /// `308 Resume Incomplete` for every chunk but the last, and `200`/`201` once the whole file
+    /// has been received. An empty `chunk` instead queries the session's status (`Content-Range:
+    /// bytes */total`) without uploading anything.
+    fn upload_chunk(
+        &self,
+        session_uri: &str,
+        chunk: &[u8],
+        start: usize,
+        total: usize,
+    ) -> Result<Response, Error> {
+        let client = hyper::Client::with_connector(hyper::net::HttpsConnector::new(
+            hyper_rustls::TlsClient::new(),
+        ));
+
+        let range = if chunk.is_empty() {
+            format!("bytes */{}", total)
+        } else {
+            format!("bytes {}-{}/{}", start, start + chunk.len() - 1, total)
         };
 
-        self.hub
-            .files()
-            .update(file, &id)
-            .add_scope(drive3::Scope::Full)
-            .upload_resumable(DummyFile::new(data), mime_guess.parse().unwrap())
+        let mut headers = hyper::header::Headers::new();
+        headers.set(hyper::header::ContentLength(chunk.len() as u64));
+        headers.set_raw("Content-Range", vec![range.into_bytes()]);
+
+        client
+            .put(session_uri)
+            .headers(headers)
+            .body(chunk)
+            .send()
             .map_err(|e| err_msg(format!("{:#?}", e)))
     }
 
+    /// Queries a resumable session's committed offset via an empty PUT (`Content-Range: bytes
+    /// */total`), so an upload interrupted mid-chunk resumes from the byte Drive actually
+    /// acknowledged instead of restarting from scratch.
+    fn query_upload_status(&self, session_uri: &str, total: usize) -> Result<usize, Error> {
+        let response = self.upload_chunk(session_uri, &[], 0, total)?;
+
+        match response.status.as_u16() {
+            200 | 201 => Ok(total),
+            308 => Ok(response
+                .headers
+                .get_raw("Range")
+                .and_then(|values| values.first())
+                .and_then(|bytes| std::str::from_utf8(bytes).ok())
+                .and_then(|range| range.rsplit('-').next())
+                .and_then(|last_byte| last_byte.parse::<usize>().ok())
+                .map(|last_byte| last_byte + 1)
+                .unwrap_or(0)),
+            status => Err(err_msg(format!(
+                "upload status query for session {} failed with status {}",
+                session_uri, status
+            ))),
+        }
+    }
+
+    /// Uploads `path`'s content to `file_id` via a resumable session, reading it from disk
+    /// `Config::upload_chunk_size_bytes` at a time rather than holding the whole file in memory.
+    /// On a failed/interrupted chunk, queries the server's committed offset and resumes from
+    /// there instead of restarting the upload.
+    fn upload_resumable_streamed(
+        &mut self,
+        file_id: &str,
+        path: &Path,
+        mime_type: &str,
+    ) -> Result<(), Error> {
+        let total = fs::metadata(path)?.len() as usize;
+        let chunk_size = self.config.upload_chunk_size_bytes() as usize;
+
+        let session_uri = self.start_resumable_session(file_id, mime_type)?;
+        let mut file = fs::File::open(path)?;
+        let mut start = 0;
+
+        loop {
+            let end = cmp::min(start + chunk_size, total);
+            let mut buffer = vec![0u8; end - start];
+            file.seek(SeekFrom::Start(start as u64))?;
+            file.read_exact(&mut buffer)?;
+
+            match self.upload_chunk(&session_uri, &buffer, start, total) {
+                Ok(response) => match response.status {
+                    hyper::status::StatusCode::Ok | hyper::status::StatusCode::Created => {
+                        start = end;
+                    }
+                    status if status.as_u16() == 308 => {
+                        start = end;
+                    }
+                    status => {
+                        warn!(
+                            "chunk upload for {} failed with status {:?}; querying the server's \
+                             committed offset to resume instead of restarting",
+                            file_id, status
+                        );
+                        start = self.query_upload_status(&session_uri, total)?;
+                    }
+                },
+                Err(e) => {
+                    warn!(
+                        "chunk upload for {} failed: {:?}; querying the server's committed \
+                         offset to resume instead of restarting",
+                        file_id, e
+                    );
+                    start = self.query_upload_status(&session_uri, total)?;
+                }
+            }
+
+            if start >= total {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns the size and capacity of the Drive account. In some cases, the limit can be absent.
     pub fn size_and_capacity(&mut self) -> Result<(u64, Option<u64>), Error> {
         let (_response, about) = self
@@ -0,0 +1,239 @@
+//! Transparent, per-mount content encryption.
+//!
+//! A file's content is split into fixed-size plaintext `BLOCK_SIZE` blocks, each sealed with its
+//! own AEAD tag under a nonce derived from a random per-file base nonce plus the block's index.
+//! Deriving nonces this way means only a small header (magic, format version, base nonce) needs
+//! to be stored once per file rather than once per block, while a byte range still only ever has
+//! to decrypt the blocks it actually overlaps -- which is what lets this compose with
+//! `DriveFacade`'s ranged reads instead of requiring the whole file up front.
+//!
+//! Files written before encryption was enabled don't start with `MAGIC` and are left alone
+//! (`parse_header` returns `None` for them, and callers fall back to treating them as plaintext).
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use failure::{err_msg, Error};
+use rand::RngCore;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Marks a file as encrypted by this module, as opposed to a plaintext file predating encryption.
+const MAGIC: &[u8; 4] = b"GCSE";
+
+/// Bumped if the header or block layout ever changes incompatibly.
+const FORMAT_VERSION: u8 = 1;
+
+/// Random per-file value stored in the header; combined with a block index to derive that
+/// block's nonce (see `block_nonce`).
+pub const BASE_NONCE_LEN: usize = 8;
+
+/// AES-GCM's standard nonce size: `BASE_NONCE_LEN` bytes of `base_nonce` plus a 4-byte
+/// big-endian block index.
+const NONCE_LEN: usize = 12;
+
+/// Size of the AEAD authentication tag AES-256-GCM appends to every sealed block.
+const TAG_LEN: usize = 16;
+
+/// `[MAGIC][FORMAT_VERSION][base_nonce]`.
+pub const HEADER_LEN: usize = MAGIC.len() + 1 + BASE_NONCE_LEN;
+
+/// Plaintext bytes per block. Chosen to keep per-read overhead low (most reads touch a handful
+/// of blocks) while still bounding how much any single read has to decrypt.
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Bytes a single sealed block takes up on Drive.
+const CIPHERTEXT_BLOCK_SIZE: usize = BLOCK_SIZE + TAG_LEN;
+
+/// The per-mount symmetric content-encryption key, loaded from (or generated into) a small file
+/// referenced by `Config::encryption_key_file`. Analogous to `Config::token_store`: the key
+/// itself lives outside of `DriveFacade`/Drive entirely, so a leaked Drive token alone can't
+/// decrypt anything.
+#[derive(Clone)]
+pub struct KeyStore {
+    key: [u8; 32],
+}
+
+impl KeyStore {
+    /// Loads the key from `path` if it already exists, otherwise generates a random one and
+    /// persists it there (mode `0600`) before returning it.
+    pub fn load_or_create(path: &Path) -> Result<Self, Error> {
+        if path.exists() {
+            let encoded = fs::read_to_string(path)?;
+            let bytes = STANDARD
+                .decode(encoded.trim())
+                .map_err(|e| err_msg(format!("key file {:?} is not valid base64: {}", path, e)))?;
+
+            if bytes.len() != 32 {
+                return Err(err_msg(format!(
+                    "key file {:?} does not contain a 32-byte key",
+                    path
+                )));
+            }
+
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&bytes);
+            return Ok(KeyStore { key });
+        }
+
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, STANDARD.encode(key))?;
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+
+        Ok(KeyStore { key })
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::from_slice(&self.key))
+    }
+}
+
+/// Derives block `block_index`'s nonce from the file's `base_nonce`.
+fn block_nonce(base_nonce: &[u8], block_index: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..BASE_NONCE_LEN].copy_from_slice(base_nonce);
+    nonce[BASE_NONCE_LEN..].copy_from_slice(&(block_index as u32).to_be_bytes());
+    nonce
+}
+
+/// Test-only window onto `block_nonce`, so nonce derivation can be unit-tested directly.
+#[cfg(test)]
+pub fn block_nonce_for_testing(base_nonce: &[u8], block_index: u64) -> [u8; NONCE_LEN] {
+    block_nonce(base_nonce, block_index)
+}
+
+/// Reads from `src` until `buf` is completely full or EOF, returning how much was actually read.
+/// Plain `Read::read` may return fewer bytes than requested even before EOF.
+fn read_full(src: &mut fs::File, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut total = 0;
+    while total < buf.len() {
+        let n = src.read(&mut buf[total..])?;
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    Ok(total)
+}
+
+/// Streams `src`'s plaintext into `dest` as a sequence of sealed `BLOCK_SIZE` blocks behind a
+/// fresh random header, without ever holding more than one block of either in memory.
+pub fn encrypt_file(key_store: &KeyStore, src: &mut fs::File, dest: &mut fs::File) -> Result<(), Error> {
+    let mut base_nonce = [0u8; BASE_NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut base_nonce);
+
+    dest.write_all(MAGIC)?;
+    dest.write_all(&[FORMAT_VERSION])?;
+    dest.write_all(&base_nonce)?;
+
+    let cipher = key_store.cipher();
+    let mut buf = vec![0u8; BLOCK_SIZE];
+    let mut block_index: u64 = 0;
+
+    loop {
+        let read = read_full(src, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+
+        let nonce = block_nonce(&base_nonce, block_index);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), &buf[..read])
+            .map_err(|e| err_msg(format!("failed to encrypt block {}: {:?}", block_index, e)))?;
+        dest.write_all(&ciphertext)?;
+
+        block_index += 1;
+        if read < BLOCK_SIZE {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `data` (expected to be `HEADER_LEN` bytes, but tolerates fewer) as an encryption
+/// header, returning the base nonce it encodes or `None` if `data` doesn't start with `MAGIC` --
+/// i.e. it's a file written before encryption was enabled.
+pub fn parse_header(data: &[u8]) -> Option<[u8; BASE_NONCE_LEN]> {
+    if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC || data[MAGIC.len()] != FORMAT_VERSION {
+        return None;
+    }
+
+    let mut base_nonce = [0u8; BASE_NONCE_LEN];
+    base_nonce.copy_from_slice(&data[MAGIC.len() + 1..HEADER_LEN]);
+    Some(base_nonce)
+}
+
+/// What `DriveFacade::read_encrypted` needs in order to fetch exactly the ciphertext blocks that
+/// overlap a plaintext `[offset, offset + size)` range.
+pub struct RangePlan {
+    /// Index (0-based) of the first block covering `offset`.
+    pub first_block_index: u64,
+    /// Where `first_block_index` starts, in plaintext bytes from the start of the content (i.e.
+    /// excluding `HEADER_LEN`).
+    pub block_start: usize,
+    /// How many plaintext bytes into the first decrypted block `offset` actually falls.
+    pub skip_prefix: usize,
+    /// How many ciphertext bytes to fetch, starting at `HEADER_LEN + block_start`.
+    pub ciphertext_len: usize,
+}
+
+/// Plans the ciphertext fetch needed to satisfy a plaintext read of `[offset, offset + size)`.
+pub fn plan_range(offset: usize, size: usize) -> RangePlan {
+    let first_block_index = (offset / BLOCK_SIZE) as u64;
+    let block_start = first_block_index as usize * BLOCK_SIZE;
+    let skip_prefix = offset - block_start;
+
+    let last_block_index = if size == 0 {
+        first_block_index
+    } else {
+        ((offset + size - 1) / BLOCK_SIZE) as u64
+    };
+    let num_blocks = (last_block_index - first_block_index + 1) as usize;
+
+    RangePlan {
+        first_block_index,
+        block_start,
+        skip_prefix,
+        ciphertext_len: num_blocks * CIPHERTEXT_BLOCK_SIZE,
+    }
+}
+
+/// Decrypts a contiguous run of whole sealed blocks, starting at `first_block_index`. `ciphertext`
+/// may be shorter than a full multiple of `CIPHERTEXT_BLOCK_SIZE` (e.g. Drive served less than
+/// asked for near EOF); a trailing remainder too short to contain even an empty block's tag is
+/// dropped rather than treated as an error.
+pub fn decrypt_blocks(
+    key_store: &KeyStore,
+    base_nonce: &[u8; BASE_NONCE_LEN],
+    first_block_index: u64,
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cipher = key_store.cipher();
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+
+    for (i, chunk) in ciphertext.chunks(CIPHERTEXT_BLOCK_SIZE).enumerate() {
+        if chunk.len() <= TAG_LEN {
+            break;
+        }
+
+        let block_index = first_block_index + i as u64;
+        let nonce = block_nonce(base_nonce, block_index);
+        let block = cipher
+            .decrypt(Nonce::from_slice(&nonce), chunk)
+            .map_err(|e| err_msg(format!("failed to decrypt block {}: {:?}", block_index, e)))?;
+        plaintext.extend_from_slice(&block);
+    }
+
+    Ok(plaintext)
+}
@@ -2,6 +2,10 @@ use failure::{err_msg, Error};
 use fuser::{FileAttr, FileType};
 use id_tree::NodeId;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 type Inode = u64;
 type DriveId = String;
@@ -14,14 +18,30 @@ type DriveId = String;
 /// an additional numeric identifier for this particular file. This identifier influences the
 /// reported file name (e.g some_file.txt.1)
 /// `drive_file`: the associated Drive file (if one exists)
+/// `trashed_parent_id`: the Drive id of the parent this file was moved out of when it was sent to
+/// Trash, so that it can be restored to the same place later
+/// `symlink_target`: for a `FileType::Symlink` (a Drive shortcut, see `SHORTCUT_MIME_TYPE`), the
+/// path it points to. Mirrored onto the Drive shortcut's `description` field so it survives a
+/// remount without any local-only state.
+/// `export_mime_type`: for a Google-native document, the concrete format *this* `File` reads as
+/// (e.g. `application/pdf`), passed straight to `DriveFacade::read`'s export endpoint. Distinct
+/// from `mime_type()`, which always reports the Drive-native type; two `File`s sharing a
+/// `drive_id` (see `Config::export_all_formats`) differ only in this field. `None` for files with
+/// their own raw bytes.
 #[derive(Debug, Clone)]
 pub struct File {
     pub name: String,
     pub attr: FileAttr,
     pub identical_name_id: Option<usize>,
     pub drive_file: Option<drive3::api::File>,
+    pub trashed_parent_id: Option<String>,
+    pub symlink_target: Option<String>,
+    pub export_mime_type: Option<String>,
 }
 
+/// The MIME type Drive uses for shortcuts, i.e. Drive's equivalent of a symlink.
+pub const SHORTCUT_MIME_TYPE: &str = "application/vnd.google-apps.shortcut";
+
 /// Specifies multiple ways of identifying a file:
 ///
 /// * by inode
@@ -38,35 +58,122 @@ pub enum FileId {
     ParentAndName { parent: Inode, name: String },
 }
 
+/// Controls how `File::name()` renders the suffix for a file whose `identical_name_id` is `Some`.
+/// `recalculate_duplicate_suffixes_for_parent` is still the only thing that assigns the numeric
+/// id; a scheme only controls how that id is turned into a displayed name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuffixScheme {
+    /// `document.pdf` -> `document.pdf.2` (the original behavior).
+    TrailingDot,
+    /// `document.pdf` -> `document (2).pdf`, matching macOS/Windows Explorer conventions.
+    ParenBeforeExtension,
+    /// `document.pdf` -> `document-a1b2c3d4.pdf`, a hash of the Drive id that stays stable even if
+    /// a sibling is added or removed and the numeric id gets reassigned.
+    DriveIdHash,
+}
+
+impl SuffixScheme {
+    fn from_tag(tag: usize) -> Self {
+        match tag {
+            1 => SuffixScheme::ParenBeforeExtension,
+            2 => SuffixScheme::DriveIdHash,
+            _ => SuffixScheme::TrailingDot,
+        }
+    }
+
+    fn tag(self) -> usize {
+        match self {
+            SuffixScheme::TrailingDot => 0,
+            SuffixScheme::ParenBeforeExtension => 1,
+            SuffixScheme::DriveIdHash => 2,
+        }
+    }
+
+    /// Renders `base` (with optional extension `ext`, already split off the trailing `.ext`) with
+    /// a suffix for duplicate index `id`. `drive_id` is consulted only by `DriveIdHash`.
+    fn render(self, base: &str, ext: Option<&str>, id: usize, drive_id: Option<&str>) -> String {
+        match self {
+            SuffixScheme::TrailingDot => match ext {
+                Some(ext) => format!("{}.{}.{}", base, ext, id),
+                None => format!("{}.{}", base, id),
+            },
+            SuffixScheme::ParenBeforeExtension => match ext {
+                Some(ext) => format!("{} ({}).{}", base, id, ext),
+                None => format!("{} ({})", base, id),
+            },
+            SuffixScheme::DriveIdHash => {
+                let mut hasher = DefaultHasher::new();
+                drive_id.unwrap_or_default().hash(&mut hasher);
+                let hash = format!("{:x}", hasher.finish())
+                    .chars()
+                    .take(8)
+                    .collect::<String>();
+
+                match ext {
+                    Some(ext) => format!("{}-{}.{}", base, hash, ext),
+                    None => format!("{}-{}", base, hash),
+                }
+            }
+        }
+    }
+}
+
+/// The process-wide suffix scheme used by `File::name()`. `name()` is called in many places that
+/// don't have access to a `Config`/`FileManager`, so this is configured globally rather than
+/// threaded through every call site.
+static SUFFIX_SCHEME: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the process-wide suffix scheme used by `File::name()` for duplicate files.
+pub fn set_suffix_scheme(scheme: SuffixScheme) {
+    SUFFIX_SCHEME.store(scheme.tag(), Ordering::Relaxed);
+}
+
 lazy_static! {
-    static ref EXTENSIONS: HashMap<&'static str, &'static str> = hashmap! {
-            "application/vnd.google-apps.document" => "#.odt",
-            "application/vnd.google-apps.presentation" => "#.odp",
-            "application/vnd.google-apps.spreadsheet" => "#.ods",
-            "application/vnd.google-apps.drawing" => "#.png",
-            "application/vnd.google-apps.site" => "#.txt",
+    /// Google-native document mime types that have no raw bytes of their own and must be
+    /// exported (see `DriveFacade::export_mime_type`/`Config::export_formats`) rather than
+    /// downloaded as-is.
+    static ref GOOGLE_WORKSPACE_MIME_TYPES: HashSet<&'static str> = hashset! {
+        "application/vnd.google-apps.document",
+        "application/vnd.google-apps.presentation",
+        "application/vnd.google-apps.spreadsheet",
+        "application/vnd.google-apps.drawing",
+        "application/vnd.google-apps.site",
     };
 }
 
 impl File {
-    /// Creates a new file using a Drive file as a template.
+    /// Creates a new file using a Drive file as a template. `export_mime_type`, if given, is the
+    /// concrete format (see `Config::export_formats`/`DriveFacade::export_mime_type`) this file
+    /// will be exported as on `read`, and gets its matching extension appended to the displayed
+    /// name (e.g. a `application/vnd.google-apps.document` exported to `.docx` is shown as
+    /// `report.docx`); native Drive files (with actual bytes) should pass `None`. Google-native
+    /// documents report no `size` of their own, so `size` falls back to a best-effort placeholder.
     pub fn from_drive_file(
         inode: Inode,
         drive_file: drive3::api::File,
-        add_extension: bool,
+        export_mime_type: Option<String>,
     ) -> Self {
         let mut size = drive_file
             .size
             .map(|size| u64::try_from(size).unwrap_or_default())
             .unwrap_or(10 * 1024 * 1024);
 
-        let kind =
-            if drive_file.mime_type == Some(String::from("application/vnd.google-apps.folder")) {
-                size = 512;
-                FileType::Directory
-            } else {
-                FileType::RegularFile
-            };
+        let symlink_target = if drive_file.mime_type.as_deref() == Some(SHORTCUT_MIME_TYPE) {
+            drive_file.description.clone()
+        } else {
+            None
+        };
+
+        let kind = if drive_file.mime_type == Some(String::from("application/vnd.google-apps.folder"))
+        {
+            size = 512;
+            FileType::Directory
+        } else if symlink_target.is_some() {
+            size = symlink_target.as_ref().map(|t| t.len() as u64).unwrap_or(0);
+            FileType::Symlink
+        } else {
+            FileType::RegularFile
+        };
 
         let times: Vec<std::time::SystemTime> = [&drive_file.created_time,
             &drive_file.modified_time,
@@ -109,14 +216,12 @@ impl File {
         //     .map(|owner| owner.email_address.unwrap())
         //     .collect();
 
-        if add_extension {
-            let ext = drive_file
-                .mime_type
-                .clone()
-                .and_then(|t| EXTENSIONS.get::<str>(&t));
-            if let Some(ext_value) = ext {
-                filename = format!("{}{}", filename, ext_value);
-            }
+        if let Some(ext) = export_mime_type
+            .as_deref()
+            .and_then(mime_guess::get_mime_extensions_str)
+            .and_then(|exts| exts.first())
+        {
+            filename = format!("{}.{}", filename, ext);
         }
 
         File {
@@ -125,6 +230,9 @@ impl File {
             attr,
             identical_name_id: None,
             drive_file: Some(drive_file),
+            trashed_parent_id: None,
+            symlink_target,
+            export_mime_type,
         }
     }
 
@@ -165,15 +273,23 @@ impl File {
         self.drive_file
             .as_ref()
             .and_then(|f| f.mime_type.clone())
-            .map(|t| EXTENSIONS.contains_key::<str>(&t))
+            .map(|t| GOOGLE_WORKSPACE_MIME_TYPES.contains::<str>(&t))
             == Some(true)
     }
 
     pub fn name(&self) -> String {
-        match self.identical_name_id {
-            Some(id) => format!("{}.{}", self.name, id),
-            None => self.name.clone(),
-        }
+        let id = match self.identical_name_id {
+            Some(id) => id,
+            None => return self.name.clone(),
+        };
+
+        let (base, ext) = match self.name.rfind('.') {
+            Some(i) if i > 0 => (&self.name[..i], Some(&self.name[i + 1..])),
+            _ => (self.name.as_str(), None),
+        };
+
+        let scheme = SuffixScheme::from_tag(SUFFIX_SCHEME.load(Ordering::Relaxed));
+        scheme.render(base, ext, id, self.drive_id().as_deref())
     }
 
     pub fn inode(&self) -> Inode {
@@ -213,4 +329,80 @@ impl File {
 
         self.drive_file.as_ref().unwrap().mime_type.clone()
     }
+
+    /// The MD5 checksum Drive reports for this file's content, if any (Google-native documents,
+    /// for instance, don't have one).
+    pub fn md5_checksum(&self) -> Option<String> {
+        self.drive_file.as_ref()?;
+
+        self.drive_file.as_ref().unwrap().md5_checksum.clone()
+    }
+
+    /// The email addresses of this file's Drive owners, if any.
+    pub fn owners(&self) -> Vec<String> {
+        self.drive_file
+            .as_ref()
+            .and_then(|f| f.owners.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|owner| owner.email_address)
+            .collect()
+    }
+
+    /// Whether this file has been shared with other users.
+    pub fn is_shared(&self) -> bool {
+        self.drive_file
+            .as_ref()
+            .and_then(|f| f.shared)
+            .unwrap_or(false)
+    }
+
+    /// Whether this file is starred.
+    pub fn is_starred(&self) -> bool {
+        self.drive_file
+            .as_ref()
+            .and_then(|f| f.starred)
+            .unwrap_or(false)
+    }
+
+    /// A link for opening this file in a browser, if Drive reports one.
+    pub fn web_view_link(&self) -> Option<String> {
+        self.drive_file.as_ref()?;
+
+        self.drive_file.as_ref().unwrap().web_view_link.clone()
+    }
+
+    /// The `user.drive.*` extended attributes this file can currently answer, as `(name, value)`
+    /// pairs. Used by `getxattr`/`listxattr`; keys whose value would be empty/absent (e.g. no
+    /// `md5_checksum` for a Google-native document) are omitted entirely, matching how a real
+    /// xattr namespace only lists attributes that are actually set.
+    pub fn drive_xattrs(&self) -> Vec<(&'static str, String)> {
+        let mut attrs = Vec::new();
+
+        if self.drive_file.is_none() {
+            return attrs;
+        }
+
+        if let Some(id) = self.drive_id() {
+            attrs.push(("user.drive.id", id));
+        }
+        if let Some(mime_type) = self.mime_type() {
+            attrs.push(("user.drive.mime_type", mime_type));
+        }
+        if let Some(md5) = self.md5_checksum() {
+            attrs.push(("user.drive.md5_checksum", md5));
+        }
+        let owners = self.owners();
+        if !owners.is_empty() {
+            attrs.push(("user.drive.owners", owners.join(",")));
+        }
+        attrs.push(("user.drive.shared", self.is_shared().to_string()));
+        attrs.push(("user.drive.trashed", self.is_trashed().to_string()));
+        attrs.push(("user.drive.starred", self.is_starred().to_string()));
+        if let Some(link) = self.web_view_link() {
+            attrs.push(("user.drive.web_view_link", link));
+        }
+
+        attrs
+    }
 }
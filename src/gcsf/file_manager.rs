@@ -1,4 +1,5 @@
-use super::{Config, File, FileId};
+use super::snapshot;
+use super::{Config, File, FileId, Permission};
 use drive3;
 use failure::{err_msg, Error};
 use fuse::{FileAttr, FileType};
@@ -6,9 +7,14 @@ use id_tree::InsertBehavior::*;
 use id_tree::MoveBehavior::*;
 use id_tree::RemoveBehavior::*;
 use id_tree::{Node, NodeId, Tree, TreeBuilder};
+use regex::RegexSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::LinkedList;
+use std::cmp::Ordering;
+use std::error::Error as StdError;
 use std::fmt;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 use time::Timespec;
 use DriveFacade;
@@ -16,6 +22,25 @@ use DriveFacade;
 pub type Inode = u64;
 pub type DriveId = String;
 
+/// How to automatically resolve a group of content-identical files (same size and
+/// `md5_checksum`, see `FileManager::content_duplicates`). Opt-in and `None` by default, since
+/// this trashes files on Drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Never touch content-duplicate groups.
+    None,
+    /// Keep the member with the most recent `mtime`, trash the rest.
+    KeepNewest,
+    /// Keep the member with the oldest `mtime`, trash the rest.
+    KeepOldest,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        DuplicatePolicy::None
+    }
+}
+
 const ROOT_INODE: Inode = 1;
 const TRASH_INODE: Inode = 2;
 const SHARED_INODE: Inode = 3;
@@ -56,24 +81,107 @@ pub struct FileManager {
     pub sync_interval: Duration,
     
     /// Renamed duplicate files if enabled
-    pub rename_identical_files: bool, 
+    pub rename_identical_files: bool,
+
+    /// Patterns matched against a file's full path; matching files are never mounted.
+    excludes: RegexSet,
+
+    /// Patterns matched against a file's full path; if non-empty, only matching files are mounted.
+    includes: RegexSet,
+
+    /// Drive ids that were excluded (directly or because an ancestor was excluded), so that their
+    /// descendants can be excluded too even once the ancestor itself is no longer reachable.
+    excluded_drive_ids: HashSet<DriveId>,
+
+    /// Last time a directory's children were known to have changed, used by `dir_is_stale` to
+    /// let FUSE readdir/getattr skip a targeted refresh when the listing is already fresh.
+    dir_mtimes: HashMap<Inode, SystemTime>,
+
+    /// Policy for automatically resolving groups of content-identical files. `None` unless
+    /// explicitly opted into via `set_duplicate_policy`.
+    duplicate_policy: DuplicatePolicy,
+
+    /// Whether a Google-native document gets a sibling `File` per export format (see
+    /// `Config::export_all_formats`) instead of just the configured default.
+    export_all_formats: bool,
+
+    /// Where `sync()` periodically re-persists the snapshot, if it was loaded from (or is meant
+    /// to be written to) one. `None` means snapshotting is disabled for this manager.
+    snapshot_path: Option<PathBuf>,
 
     last_inode: Inode,
 }
 
+/// Returned (wrapped in `Error`) by `exchange` when the second of its two Drive-side `move_to`
+/// calls fails after the first already went through. `resynced` tells a caller whether `exchange`
+/// managed to put the local tree and Drive back the way they were before it was called
+/// (`true`, safe to just report the original failure and retry), or whether the rollback itself
+/// also failed (`false`, meaning the local tree and Drive are now genuinely out of sync and need a
+/// resync before anything else touches either `a` or `b`). `downcast_ref`-able so callers can tell
+/// this apart from an ordinary Drive-API failure, the way `Filesystem::release` does for
+/// `FlushConflict`.
+#[derive(Debug)]
+pub struct ExchangeConflict {
+    pub a: DriveId,
+    pub b: DriveId,
+    pub resynced: bool,
+    pub cause: Error,
+}
+
+impl fmt::Display for ExchangeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.resynced {
+            write!(
+                f,
+                "exchanging {} and {} failed ({}), but the local tree and Drive were put back in sync",
+                self.a, self.b, self.cause
+            )
+        } else {
+            write!(
+                f,
+                "exchanging {} and {} failed ({}), and rolling back left the local tree and Drive \
+                 out of sync -- a resync is needed before touching either file again",
+                self.a, self.b, self.cause
+            )
+        }
+    }
+}
+
+impl StdError for ExchangeConflict {}
+
 impl FileManager {
     /// Creates a new FileManager with a specific `sync_interval` and an injected `DriveFacade`.
     /// Also populates the manager's file tree with files contained in "My Drive" and "Trash".
-    pub fn with_drive_facade(rename_identical_files: bool, sync_interval: Duration, df: DriveFacade) -> Result<Self, Error> {
+    ///
+    /// `excludes`/`includes` are regex patterns matched against a file's full path within the
+    /// tree (see `Config::excludes`/`Config::includes`); files that don't pass the filter are
+    /// never added to `files`/`tree` in the first place.
+    pub fn with_drive_facade(
+        rename_identical_files: bool,
+        sync_interval: Duration,
+        df: DriveFacade,
+        excludes: Vec<String>,
+        includes: Vec<String>,
+        export_all_formats: bool,
+    ) -> Result<Self, Error> {
         let mut manager = FileManager {
             tree: TreeBuilder::new().with_node_capacity(500).build(),
             files: HashMap::new(),
             node_ids: HashMap::new(),
             drive_ids: HashMap::new(),
             last_sync: SystemTime::now(),
-            rename_identical_files: rename_identical_files,
+            rename_identical_files,
             sync_interval,
             df,
+            excludes: RegexSet::new(&excludes)
+                .map_err(|e| err_msg(format!("Invalid `excludes` pattern: {}", e)))?,
+            includes: RegexSet::new(&includes)
+                .map_err(|e| err_msg(format!("Invalid `includes` pattern: {}", e)))?,
+            excluded_drive_ids: HashSet::new(),
+            dir_mtimes: HashMap::new(),
+            duplicate_policy: DuplicatePolicy::default(),
+            export_all_formats,
+            snapshot_path: None,
             last_inode: 2,
         };
 
@@ -86,6 +194,204 @@ impl FileManager {
         Ok(manager)
     }
 
+    /// Like `with_drive_facade`, but first tries to load a previously persisted snapshot of the
+    /// file tree from `snapshot_path`, to avoid a full Drive enumeration on every mount. Falls
+    /// back to a cold `populate()`/`populate_trash()` pass if the snapshot is missing, unreadable,
+    /// or was written by an incompatible schema version, and writes a fresh one afterwards so
+    /// that the next mount can benefit from it. The resulting manager remembers `snapshot_path`
+    /// and re-persists itself there periodically during `sync()` (see
+    /// `Gcsf`'s unmount/SIGTERM handling in `main.rs` for the save-on-exit side of this).
+    pub fn with_drive_facade_and_snapshot(
+        rename_identical_files: bool,
+        sync_interval: Duration,
+        df: DriveFacade,
+        excludes: Vec<String>,
+        includes: Vec<String>,
+        export_all_formats: bool,
+        snapshot_path: Option<&Path>,
+    ) -> Result<Self, Error> {
+        if let Some(path) = snapshot_path {
+            match snapshot::load(path) {
+                Ok(snap) => {
+                    let mut manager = FileManager::from_snapshot(
+                        snap,
+                        rename_identical_files,
+                        sync_interval,
+                        df,
+                        excludes,
+                        includes,
+                        export_all_formats,
+                    )?;
+                    manager.snapshot_path = Some(path.to_path_buf());
+
+                    // The persisted `last_sync` drives a delta catch-up through `sync()` instead
+                    // of a cold repopulate.
+                    if let Err(e) = manager.sync() {
+                        debug!("No changes to apply after loading snapshot: {}", e);
+                    }
+                    return Ok(manager);
+                }
+                Err(e) => {
+                    debug!(
+                        "Could not load snapshot from {:?}, falling back to a full populate: {}",
+                        path, e
+                    );
+                }
+            }
+        }
+
+        let mut manager = FileManager::with_drive_facade(
+            rename_identical_files,
+            sync_interval,
+            df,
+            excludes,
+            includes,
+            export_all_formats,
+        )?;
+        if let Some(path) = snapshot_path {
+            manager.snapshot_path = Some(path.to_path_buf());
+            if let Err(e) = manager.save_snapshot(path) {
+                warn!("Could not persist snapshot to {:?}: {}", path, e);
+            }
+        }
+        Ok(manager)
+    }
+
+    /// Serializes the current tree and file state to `path`, compressed with zstd. Used to skip
+    /// the full Drive enumeration on the next mount.
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), Error> {
+        let mut nodes = Vec::with_capacity(self.files.len());
+
+        if let Some(root_id) = self.tree.root_node_id() {
+            let mut stack: Vec<(NodeId, Option<Inode>)> = vec![(root_id.clone(), None)];
+
+            while let Some((node_id, parent)) = stack.pop() {
+                let node = self.tree.get(&node_id)?;
+                let inode = *node.data();
+
+                if let Some(file) = self.files.get(&inode) {
+                    nodes.push(snapshot::make_node(inode, parent, file));
+                }
+
+                for child_id in node.children() {
+                    stack.push((child_id.clone(), Some(inode)));
+                }
+            }
+        }
+
+        let snap = snapshot::Snapshot::new(
+            nodes,
+            self.drive_ids.clone(),
+            self.last_inode,
+            self.last_sync,
+            self.df.persisted_changes_token(),
+        );
+        snapshot::save(path, &snap)
+    }
+
+    /// If this manager was loaded from (or is bound to) a snapshot path, re-persists it there.
+    /// Called after every successful `sync()` so that a crash between mounts loses at most one
+    /// sync interval's worth of progress, and should also be called on unmount/SIGTERM.
+    pub fn save_snapshot_if_configured(&self) {
+        if let Some(path) = self.snapshot_path.clone() {
+            if let Err(e) = self.save_snapshot(&path) {
+                warn!("Could not persist snapshot to {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// Reconstructs a `FileManager` from a previously-saved snapshot, skipping `populate()`
+    /// entirely. Nodes are re-inserted parent-first so every `UnderNode` insertion has a live
+    /// parent id.
+    fn from_snapshot(
+        snap: snapshot::Snapshot,
+        rename_identical_files: bool,
+        sync_interval: Duration,
+        mut df: DriveFacade,
+        excludes: Vec<String>,
+        includes: Vec<String>,
+        export_all_formats: bool,
+    ) -> Result<Self, Error> {
+        df.restore_changes_token(snap.changes_token.clone());
+
+        let mut manager = FileManager {
+            tree: TreeBuilder::new()
+                .with_node_capacity(snap.nodes.len())
+                .build(),
+            files: HashMap::new(),
+            node_ids: HashMap::new(),
+            drive_ids: snap.drive_ids,
+            last_sync: snap.last_sync(),
+            rename_identical_files,
+            sync_interval,
+            df,
+            excludes: RegexSet::new(&excludes)
+                .map_err(|e| err_msg(format!("Invalid `excludes` pattern: {}", e)))?,
+            includes: RegexSet::new(&includes)
+                .map_err(|e| err_msg(format!("Invalid `includes` pattern: {}", e)))?,
+            excluded_drive_ids: HashSet::new(),
+            dir_mtimes: HashMap::new(),
+            duplicate_policy: DuplicatePolicy::default(),
+            export_all_formats,
+            snapshot_path: None,
+            last_inode: snap.last_inode,
+        };
+
+        for stored in snap.nodes {
+            let node_id = match stored.parent {
+                Some(parent_inode) => {
+                    let parent_id = manager
+                        .node_ids
+                        .get(&parent_inode)
+                        .ok_or_else(|| {
+                            err_msg(format!(
+                                "snapshot: parent inode {} for child {} was not inserted yet \
+                                 (nodes must be stored parent-first)",
+                                parent_inode, stored.inode
+                            ))
+                        })?
+                        .clone();
+                    manager
+                        .tree
+                        .insert(Node::new(stored.inode), UnderNode(&parent_id))?
+                }
+                None => manager.tree.insert(Node::new(stored.inode), AsRoot)?,
+            };
+
+            manager.node_ids.insert(stored.inode, node_id);
+            manager
+                .files
+                .insert(stored.inode, snapshot::file_from_stored(stored));
+        }
+
+        Ok(manager)
+    }
+
+    /// Swaps in a freshly-authenticated `DriveFacade` and fully rebuilds the file tree from
+    /// scratch against Drive, the same way a fresh mount would (see `with_drive_facade`). Used by
+    /// `Gcsf::reload` (triggered by the `user.gcsf.reload` xattr) to recover from an expired OAuth
+    /// token or force consistency after out-of-band Drive edits, without a remount. `excludes`,
+    /// `includes`, `rename_identical_files` and `export_all_formats` keep whatever was configured
+    /// at mount time; only Drive-derived state (`files`, `tree`, `drive_ids`, ...) is reset.
+    pub fn reload(&mut self, df: DriveFacade) -> Result<(), Error> {
+        self.df = df;
+        self.tree = TreeBuilder::new().with_node_capacity(500).build();
+        self.files.clear();
+        self.node_ids.clear();
+        self.drive_ids.clear();
+        self.excluded_drive_ids.clear();
+        self.dir_mtimes.clear();
+        self.last_inode = 2;
+
+        self.populate()?;
+        self.populate_trash()?;
+
+        self.last_sync = SystemTime::now();
+        self.save_snapshot_if_configured();
+
+        Ok(())
+    }
+
     /// Tries to retrieve recent changes from the `DriveFacade` and apply them locally in order to
     /// maintain data consistency. Fails early if not enough time has passed since the last sync.
     pub fn sync(&mut self) -> Result<(), Error> {
@@ -111,12 +417,26 @@ impl FileManager {
             // New file. Create it locally
             if !self.contains(&id) {
                 debug!("New file. Create it locally");
-                let f = File::from_drive_file(self.next_available_inode(), drive_f.clone());
+                let export_mime = drive_f
+                    .mime_type
+                    .as_deref()
+                    .and_then(|m| self.df.export_mime_type(m));
+                let f = File::from_drive_file(
+                    self.next_available_inode(),
+                    drive_f.clone(),
+                    export_mime.clone(),
+                );
                 debug!("newly created file: {:#?}", &f);
 
+                if self.is_path_excluded(&f) {
+                    debug!("Skipping {:?}: excluded by configured patterns", &f.name);
+                    continue;
+                }
+
                 let parent = f.drive_parent().unwrap();
                 debug!("drive parent: {:#?}", &parent);
-                self.add_file_locally(f, Some(FileId::DriveId(parent)))?;
+                self.add_file_locally(f, Some(FileId::DriveId(parent.clone())))?;
+                self.add_export_siblings(&drive_f, export_mime.as_ref(), Some(FileId::DriveId(parent)))?;
                 debug!("self.add_file_locally() finished");
             }
 
@@ -130,6 +450,17 @@ impl FileManager {
                 continue;
             }
 
+            // File was un-trashed remotely. If it's still sitting under our local Trash dir,
+            // restore it to its original parent instead of leaving it stranded there.
+            if Some(false) == drive_f.trashed && self.is_under_trash(&id) {
+                debug!("File restored from Trash remotely. Restoring it locally.");
+                let result = self.restore_from_trash(&id);
+                if result.is_err() {
+                    error!("Could not restore from trash: {:?}", result)
+                }
+                continue;
+            }
+
             // Removed file. Remove it locally.
             if let Some(true) = change.removed {
                 debug!("Removed file. Remove it locally.");
@@ -142,9 +473,13 @@ impl FileManager {
 
             // Anything else: reconstruct the file locally and move it under its parent.
             debug!("Anything else: reconstruct the file locally and move it under its parent.");
+            let export_mime = drive_f
+                .mime_type
+                .as_deref()
+                .and_then(|m| self.df.export_mime_type(m));
             let new_parent = {
                 let mut f = unwrap_or_continue!(self.get_mut_file(&id));
-                *f = File::from_drive_file(f.inode(), drive_f.clone());
+                *f = File::from_drive_file(f.inode(), drive_f.clone(), export_mime);
                 FileId::DriveId(f.drive_parent().unwrap())
             };
             let result = self.move_locally(&id, &new_parent);
@@ -153,6 +488,7 @@ impl FileManager {
             }
         }
 
+        self.save_snapshot_if_configured();
         Ok(())
     }
 
@@ -165,8 +501,20 @@ impl FileManager {
         self.add_file_locally(shared, Some(FileId::Inode(ROOT_INODE)))?;
 
         for drive_file in self.df.get_all_files(None, Some(false))? {
-            let mut file = File::from_drive_file(self.next_available_inode(), drive_file);
+            let export_mime = drive_file
+                .mime_type
+                .as_deref()
+                .and_then(|m| self.df.export_mime_type(m));
+            let mut file = File::from_drive_file(
+                self.next_available_inode(),
+                drive_file.clone(),
+                export_mime.clone(),
+            );
+            if self.is_path_excluded(&file) {
+                continue;
+            }
             self.add_file_locally(file, Some(FileId::Inode(3)))?;
+            self.add_export_siblings(&drive_file, export_mime.as_ref(), Some(FileId::Inode(3)))?;
         }
 
         let mut moves: LinkedList<(FileId, FileId)> = LinkedList::new();
@@ -194,8 +542,24 @@ impl FileManager {
         self.add_file_locally(trash.clone(), Some(FileId::DriveId(root_id.to_string())))?;
 
         for drive_file in self.df.get_all_files(None, Some(true))? {
-            let mut file = File::from_drive_file(self.next_available_inode(), drive_file);
+            let export_mime = drive_file
+                .mime_type
+                .as_deref()
+                .and_then(|m| self.df.export_mime_type(m));
+            let mut file = File::from_drive_file(
+                self.next_available_inode(),
+                drive_file.clone(),
+                export_mime.clone(),
+            );
+            if self.is_path_excluded(&file) {
+                continue;
+            }
             self.add_file_locally(file, Some(FileId::Inode(trash.inode())))?;
+            self.add_export_siblings(
+                &drive_file,
+                export_mime.as_ref(),
+                Some(FileId::Inode(trash.inode())),
+            )?;
         }
 
         Ok(())
@@ -230,6 +594,9 @@ impl FileManager {
             },
             identical_name_id: None,
             drive_file: Some(drive_file),
+            trashed_parent_id: None,
+            symlink_target: None,
+            export_mime_type: None,
         })
     }
 
@@ -256,6 +623,353 @@ impl FileManager {
             },
             identical_name_id: None,
             drive_file: None,
+            trashed_parent_id: None,
+            symlink_target: None,
+            export_mime_type: None,
+        }
+    }
+
+    /// Reconstructs `file`'s full path by walking up its Drive parent chain through already-known
+    /// ancestors. Ancestors that haven't been processed yet (or were themselves excluded) simply
+    /// stop the walk early; this is best-effort since Drive doesn't return files in parent-first
+    /// order.
+    fn path_from_root(&self, file: &File) -> String {
+        let mut segments = vec![file.name.clone()];
+        let mut parent = file.drive_parent();
+
+        while let Some(parent_id) = parent {
+            match self
+                .drive_ids
+                .get(&parent_id)
+                .and_then(|inode| self.files.get(inode))
+            {
+                // The root directory itself contributes no path segment.
+                Some(parent_file) if parent_file.inode() == ROOT_INODE => break,
+                Some(parent_file) => {
+                    segments.push(parent_file.name.clone());
+                    parent = parent_file.drive_parent();
+                }
+                None => break,
+            }
+        }
+
+        segments.reverse();
+        format!("/{}", segments.join("/"))
+    }
+
+    /// Whether `file` should be skipped based on the configured `excludes`/`includes` patterns.
+    /// A file is excluded if its reconstructed path matches an exclude pattern, if it doesn't
+    /// match any include pattern (when `includes` is non-empty), or if its parent was already
+    /// excluded -- so that excluding a directory prunes its entire subtree.
+    fn is_path_excluded(&mut self, file: &File) -> bool {
+        if let Some(parent_id) = file.drive_parent() {
+            if self.excluded_drive_ids.contains(&parent_id) {
+                if let Some(id) = file.drive_id() {
+                    self.excluded_drive_ids.insert(id);
+                }
+                return true;
+            }
+        }
+
+        let path = self.path_from_root(file);
+        let excluded = self.excludes.is_match(&path)
+            || (!self.includes.is_empty() && !self.includes.is_match(&path));
+
+        if excluded {
+            if let Some(id) = file.drive_id() {
+                self.excluded_drive_ids.insert(id);
+            }
+        }
+
+        excluded
+    }
+
+    /// Returns the set of suffixes currently in use among `parent`'s children named `name`, i.e.
+    /// every `identical_name_id` already assigned within that `(parent, name)` group.
+    fn used_suffixes(&self, parent: &FileId, name: &str) -> HashSet<usize> {
+        self.get_children(parent)
+            .map(|children| {
+                children
+                    .iter()
+                    .filter(|child| child.name == name)
+                    .filter_map(|child| child.identical_name_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the smallest positive integer suffix not currently in use among `parent`'s children
+    /// named `name`. Used to assign a new or renamed file a suffix without disturbing its siblings'
+    /// existing suffixes.
+    fn smallest_free_suffix(&self, parent: &FileId, name: &str) -> usize {
+        let used = self.used_suffixes(parent, name);
+        let mut suffix = 1;
+        while used.contains(&suffix) {
+            suffix += 1;
+        }
+        suffix
+    }
+
+    /// Re-evaluates `identical_name_id` for every child of `parent` named `name`, ordered by Drive
+    /// id: the first in that order gets no suffix, the rest get `Some(1)`, `Some(2)`, ... This
+    /// tightens the group back down after a member is deleted or moved out, so suffixes stay
+    /// minimal and unique instead of accumulating gaps.
+    fn recalculate_suffixes_for_name(&mut self, parent: &FileId, name: &str) {
+        let mut siblings: Vec<(Inode, Option<DriveId>)> = match self.get_children(parent) {
+            Some(children) => children
+                .into_iter()
+                .filter(|child| child.name == name)
+                .map(|child| (child.inode(), child.drive_id()))
+                .collect(),
+            None => return,
+        };
+
+        siblings.sort_by(|a, b| a.1.cmp(&b.1));
+
+        for (i, (inode, _)) in siblings.into_iter().enumerate() {
+            if let Some(file) = self.files.get_mut(&inode) {
+                file.identical_name_id = if i == 0 { None } else { Some(i) };
+            }
+        }
+    }
+
+    /// Re-evaluates duplicate suffixes for every distinctly-named group of children under
+    /// `parent_inode`. Call this after a file is deleted or moved out of `parent_inode` so any
+    /// group it used to belong to shrinks its suffixes back down.
+    pub fn recalculate_duplicate_suffixes_for_parent(&mut self, parent_inode: Inode) {
+        let parent = FileId::Inode(parent_inode);
+        let names: HashSet<String> = match self.get_children(&parent) {
+            Some(children) => children.into_iter().map(|child| child.name.clone()).collect(),
+            None => return,
+        };
+
+        for name in names {
+            self.recalculate_suffixes_for_name(&parent, &name);
+        }
+    }
+
+    /// Re-evaluates duplicate suffixes for every directory in the tree. More expensive than
+    /// `recalculate_duplicate_suffixes_for_parent`; mainly useful for tests and one-off repairs.
+    pub fn recalculate_all_duplicate_suffixes(&mut self) {
+        let parents: HashSet<Inode> = self
+            .node_ids
+            .values()
+            .filter_map(|node_id| self.tree.get(node_id).ok()?.parent())
+            .filter_map(|parent_node_id| self.tree.get(parent_node_id).ok())
+            .map(|parent_node| *parent_node.data())
+            .collect();
+
+        for parent_inode in parents {
+            self.recalculate_duplicate_suffixes_for_parent(parent_inode);
+        }
+    }
+
+    /// Whether directory `id`'s cached listing is older than `threshold`, i.e. whether it's worth
+    /// doing a targeted refresh of just this directory instead of relying on the next full sync.
+    /// A directory that has never been touched (or no longer exists) is always considered stale.
+    pub fn dir_is_stale(&self, id: &FileId, threshold: Duration) -> bool {
+        let inode = match self.get_inode(id) {
+            Some(inode) => inode,
+            None => return true,
+        };
+
+        match self.dir_mtimes.get(&inode) {
+            Some(mtime) => SystemTime::now()
+                .duration_since(*mtime)
+                .unwrap_or_default()
+                >= threshold,
+            None => true,
+        }
+    }
+
+    /// Records that `inode`'s children were just added to, moved, or removed, so `dir_is_stale`
+    /// can tell a fresh listing from a stale one.
+    pub fn touch_dir(&mut self, inode: Inode) {
+        self.dir_mtimes.insert(inode, SystemTime::now());
+    }
+
+    /// Reconciles a single directory's children against Drive's authoritative listing, instead of
+    /// the tree-wide `sync()`. Walks the locally cached children and the remote listing in sorted
+    /// lock-step (an ordered merge-join by Drive id, the same shape as Mercurial's dirstate status
+    /// algorithm), classifying each pairing as local-only (evicted), remote-only (added), or
+    /// present on both sides (attributes refreshed only if `modifiedTime` actually changed). Used
+    /// by `readdir` so a directory read only pays for the entries that actually changed.
+    pub fn reconcile_dir(&mut self, id: &FileId) -> Result<(), Error> {
+        let inode = self
+            .get_inode(id)
+            .ok_or_else(|| err_msg(format!("reconcile_dir: cannot find inode of {:?}", id)))?;
+        let drive_id = self
+            .get_drive_id(&FileId::Inode(inode))
+            .ok_or_else(|| err_msg(format!("reconcile_dir: cannot find drive id of {:?}", id)))?;
+
+        let mut local: Vec<(DriveId, Inode)> = self
+            .get_children(&FileId::Inode(inode))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|f| f.drive_id().map(|drive_id| (drive_id, f.inode())))
+            .collect();
+        local.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut remote: Vec<drive3::File> = self.df.get_all_files(Some(vec![drive_id]), Some(false))?;
+        remote.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut local_iter = local.into_iter().peekable();
+        let mut remote_iter = remote.into_iter().peekable();
+
+        loop {
+            let cmp = match (local_iter.peek(), remote_iter.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((local_drive_id, _)), Some(remote_file)) => {
+                    local_drive_id.cmp(remote_file.id.as_ref().unwrap())
+                }
+            };
+
+            match cmp {
+                // Local-only: Drive no longer reports this child, evict it.
+                Ordering::Less => {
+                    let (_, local_inode) = local_iter.next().unwrap();
+                    debug!("reconcile_dir: evicting stale local child inode={}", local_inode);
+                    let result = self.delete_locally(&FileId::Inode(local_inode));
+                    if result.is_err() {
+                        error!("reconcile_dir: could not delete locally: {:?}", result)
+                    }
+                }
+                // Remote-only: a child Drive has that we don't, add it.
+                Ordering::Greater => {
+                    let remote_file = remote_iter.next().unwrap();
+                    let export_mime = remote_file
+                        .mime_type
+                        .as_deref()
+                        .and_then(|m| self.df.export_mime_type(m));
+                    let f = File::from_drive_file(
+                        self.next_available_inode(),
+                        remote_file.clone(),
+                        export_mime.clone(),
+                    );
+                    let result = self.add_file_locally(f, Some(FileId::Inode(inode)));
+                    if result.is_err() {
+                        error!("reconcile_dir: could not add locally: {:?}", result)
+                    } else if let Err(e) =
+                        self.add_export_siblings(&remote_file, export_mime.as_ref(), Some(FileId::Inode(inode)))
+                    {
+                        error!("reconcile_dir: could not add export siblings: {:?}", e)
+                    }
+                }
+                // Present on both sides: only touch attrs if Drive's copy actually changed.
+                Ordering::Equal => {
+                    let (_, local_inode) = local_iter.next().unwrap();
+                    let remote_file = remote_iter.next().unwrap();
+                    let remote_mtime: SystemTime =
+                        remote_file.modified_time.clone().unwrap_or_default().into();
+
+                    let changed = self
+                        .files
+                        .get(&local_inode)
+                        .map(|f| f.attr.mtime != remote_mtime)
+                        .unwrap_or(true);
+
+                    if changed {
+                        let export_mime = remote_file
+                            .mime_type
+                            .as_deref()
+                            .and_then(|m| self.df.export_mime_type(m));
+                        if let Some(f) = self.files.get_mut(&local_inode) {
+                            *f = File::from_drive_file(local_inode, remote_file, export_mime);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.touch_dir(inode);
+        Ok(())
+    }
+
+    /// Groups files by content rather than by name: first by `attr.size`, then within each
+    /// same-size bucket by `md5_checksum` (falling back to grouping by size alone when the
+    /// checksum is absent, e.g. Google-native documents). Only groups with more than one member
+    /// are returned, since a singleton isn't a duplicate of anything.
+    pub fn content_duplicates(&self) -> HashMap<(u64, String), Vec<FileId>> {
+        let mut groups: HashMap<(u64, String), Vec<FileId>> = HashMap::new();
+
+        for file in self.files.values() {
+            let key = (file.attr.size, file.md5_checksum().unwrap_or_default());
+            groups
+                .entry(key)
+                .or_insert_with(Vec::new)
+                .push(FileId::Inode(file.inode()));
+        }
+
+        groups.retain(|_, members| members.len() > 1);
+        groups
+    }
+
+    /// Opts into automatically resolving groups of content-identical files with `apply_duplicate_policy`.
+    /// Disabled (`DuplicatePolicy::None`) by default, so a mount never silently trashes data.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    /// Applies the configured `DuplicatePolicy` to every `content_duplicates()` group that has an
+    /// actual `md5_checksum` match -- the size-only fallback bucket is informational only and is
+    /// never auto-resolved here, since equal size alone isn't a reliable signal of identical
+    /// content. Keeps the member with the newest/oldest `mtime` and moves the rest to Drive's
+    /// trash (never deletes permanently), returning the total number of bytes freed. Idempotent:
+    /// a group that's already down to one live member (or whose policy is `None`) is left alone.
+    pub fn apply_duplicate_policy(&mut self) -> Result<u64, Error> {
+        if self.duplicate_policy == DuplicatePolicy::None {
+            return Ok(0);
+        }
+
+        let mut freed_bytes = 0;
+
+        for ((_size, checksum), members) in self.content_duplicates() {
+            if checksum.is_empty() {
+                continue;
+            }
+
+            let mut live: Vec<Inode> = members
+                .into_iter()
+                .filter_map(|id| self.get_inode(&id))
+                .filter(|inode| {
+                    self.files
+                        .get(inode)
+                        .map(|file| !file.is_trashed())
+                        .unwrap_or(false)
+                })
+                .collect();
+
+            if live.len() <= 1 {
+                continue;
+            }
+
+            live.sort_by_key(|inode| self.files.get(inode).unwrap().attr.mtime);
+
+            for inode in Self::duplicates_to_trash(self.duplicate_policy, &live) {
+                let size = self.files.get(&inode).map(|f| f.attr.size).unwrap_or(0);
+                if self
+                    .move_file_to_trash(&FileId::Inode(inode), true)
+                    .is_ok()
+                {
+                    freed_bytes += size;
+                }
+            }
+        }
+
+        Ok(freed_bytes)
+    }
+
+    /// Given a content-duplicate group's live members, oldest-first by `mtime`, picks which ones
+    /// `apply_duplicate_policy` should trash under `policy`: all but the last under `KeepNewest`,
+    /// all but the first under `KeepOldest`, none under `None`. Split out of
+    /// `apply_duplicate_policy` so the selection itself can be unit-tested without touching Drive.
+    fn duplicates_to_trash(policy: DuplicatePolicy, live_oldest_first: &[Inode]) -> Vec<Inode> {
+        match policy {
+            DuplicatePolicy::KeepNewest => live_oldest_first[..live_oldest_first.len() - 1].to_vec(),
+            DuplicatePolicy::KeepOldest => live_oldest_first[1..].to_vec(),
+            DuplicatePolicy::None => Vec::new(),
         }
     }
 
@@ -333,6 +1047,13 @@ impl FileManager {
         self.files.get(&inode)
     }
 
+    /// `file`'s full path from the tree root, e.g. `/Documents/report.docx`. Public wrapper
+    /// around `path_from_root` for `main`'s `gcsf verify` command, which needs it to report which
+    /// path a checksum mismatch belongs to.
+    pub fn full_path(&self, file: &File) -> String {
+        self.path_from_root(file)
+    }
+
     pub fn get_mut_file(&mut self, id: &FileId) -> Option<&mut File> {
         let inode = self.get_inode(&id)?;
         self.files.get_mut(&inode)
@@ -355,8 +1076,26 @@ impl FileManager {
         self.df.flush(&file)
     }
 
+    /// Truncates a file's content to zero bytes, both locally (`attr.size`) and via a pending
+    /// write queued on the `DriveFacade` (applied like any other write on the next `flush()`).
+    /// Used to honor `O_TRUNC` on `open()`.
+    pub fn truncate(&mut self, id: &FileId) -> Result<(), Error> {
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive id of {:?}", id)))?;
+        self.df.truncate(drive_id);
+
+        if let Some(file) = self.get_mut_file(id) {
+            file.attr.size = 0;
+        }
+
+        Ok(())
+    }
+
     /// Adds a file to the local file tree. Does not communicate with Drive.
     fn add_file_locally(&mut self, mut file: File, parent: Option<FileId>) -> Result<(), Error> {
+        let parent_inode = parent.as_ref().and_then(|id| self.get_inode(id));
+
         let node_id = match parent {
             Some(id) => {
                 let parent_id = self.get_node_id(&id).ok_or(err_msg(
@@ -364,17 +1103,16 @@ impl FileManager {
                 ))?;
 
                 if self.rename_identical_files {
-                    let identical_filename_count = self
+                    let has_sibling_with_same_name = self
                         .get_children(&id)
                         .ok_or(err_msg(
                             "FileManager::add_file_locally() could not get file siblings",
                         ))?
-                       .iter()
-                        .filter(|child| child.name == file.name)
-                        .count();
+                        .iter()
+                        .any(|child| child.name == file.name);
 
-                    if identical_filename_count > 0 {
-                        file.identical_name_id = Some(identical_filename_count);
+                    if has_sibling_with_same_name {
+                        file.identical_name_id = Some(self.smallest_free_suffix(&id, &file.name));
                     }
                 }
 
@@ -389,6 +1127,76 @@ impl FileManager {
             .and_then(|drive_id| self.drive_ids.insert(drive_id, file.inode()));
         self.files.insert(file.inode(), file);
 
+        if let Some(parent_inode) = parent_inode {
+            self.touch_dir(parent_inode);
+        }
+
+        Ok(())
+    }
+
+    /// If `export_all_formats` is enabled, adds one sibling `File` under `parent` for every export
+    /// format `drive_file` could alternatively be rendered in (`DriveFacade::export_alternatives`),
+    /// besides whatever `default_export` the primary `File` (added separately, via
+    /// `add_file_locally`) already got. Each sibling shares `drive_file`'s Drive id but gets its
+    /// own inode and its own extension-qualified name (see `File::from_drive_file`).
+    fn add_export_siblings(
+        &mut self,
+        drive_file: &drive3::File,
+        default_export: Option<&String>,
+        parent: Option<FileId>,
+    ) -> Result<(), Error> {
+        if !self.export_all_formats {
+            return Ok(());
+        }
+
+        let mime_type = match drive_file.mime_type.as_deref() {
+            Some(mime_type) => mime_type.to_string(),
+            None => return Ok(()),
+        };
+
+        for alternative in self.df.export_alternatives(&mime_type) {
+            if Some(&alternative) == default_export {
+                continue;
+            }
+
+            let sibling = File::from_drive_file(
+                self.next_available_inode(),
+                drive_file.clone(),
+                Some(alternative),
+            );
+            self.add_export_sibling_locally(sibling, parent.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `add_file_locally`, but for an export-format sibling produced by `add_export_siblings`:
+    /// it gets its own node/inode, but is deliberately left out of `drive_ids` -- which only ever
+    /// tracks one inode per Drive id -- so the primary `File` stays the one `sync()`,
+    /// `move_file_to_trash()`, `rename()` and friends resolve for that Drive id. A sibling is
+    /// therefore a read-only export-format projection of the primary file rather than something
+    /// those operations can target directly.
+    fn add_export_sibling_locally(&mut self, file: File, parent: Option<FileId>) -> Result<(), Error> {
+        let parent_inode = parent.as_ref().and_then(|id| self.get_inode(id));
+
+        let node_id = match parent {
+            Some(id) => {
+                let parent_id = self.get_node_id(&id).ok_or(err_msg(
+                    "FileManager::add_export_sibling_locally() could not find parent by FileId",
+                ))?;
+                self.tree
+                    .insert(Node::new(file.inode()), UnderNode(&parent_id))?
+            }
+            None => self.tree.insert(Node::new(file.inode()), AsRoot)?,
+        };
+
+        self.node_ids.insert(file.inode(), node_id);
+        self.files.insert(file.inode(), file);
+
+        if let Some(parent_inode) = parent_inode {
+            self.touch_dir(parent_inode);
+        }
+
         Ok(())
     }
 
@@ -400,8 +1208,29 @@ impl FileManager {
         let target_node = self
             .get_node_id(&new_parent)
             .ok_or(err_msg("Target node doesn't exist"))?;
+        let old_parent_inode = self
+            .tree
+            .get(&current_node)?
+            .parent()
+            .cloned()
+            .and_then(|parent_node_id| self.get_inode(&FileId::NodeId(parent_node_id)));
+        let new_parent_inode = self.get_inode(new_parent);
 
         self.tree.move_node(&current_node, ToParent(&target_node))?;
+
+        if self.rename_identical_files {
+            if let Some(parent_inode) = old_parent_inode {
+                self.recalculate_duplicate_suffixes_for_parent(parent_inode);
+            }
+        }
+
+        if let Some(parent_inode) = old_parent_inode {
+            self.touch_dir(parent_inode);
+        }
+        if let Some(parent_inode) = new_parent_inode {
+            self.touch_dir(parent_inode);
+        }
+
         Ok(())
     }
 
@@ -416,12 +1245,28 @@ impl FileManager {
         let drive_id = self
             .get_drive_id(id)
             .ok_or(err_msg(format!("Cannot find drive id of {:?}", &id)))?;
+        let old_parent_inode = self
+            .tree
+            .get(&node_id)?
+            .parent()
+            .cloned()
+            .and_then(|parent_node_id| self.get_inode(&FileId::NodeId(parent_node_id)));
 
         self.tree.remove_node(node_id, DropChildren)?;
         self.files.remove(&inode);
         self.node_ids.remove(&inode);
         self.drive_ids.remove(&drive_id);
 
+        if self.rename_identical_files {
+            if let Some(parent_inode) = old_parent_inode {
+                self.recalculate_duplicate_suffixes_for_parent(parent_inode);
+            }
+        }
+
+        if let Some(parent_inode) = old_parent_inode {
+            self.touch_dir(parent_inode);
+        }
+
         Ok(())
     }
 
@@ -445,6 +1290,9 @@ impl FileManager {
         let node_id = self
             .get_node_id(id)
             .ok_or(err_msg(format!("Cannot find node_id of {:?}", &id)))?;
+        let inode = self
+            .get_inode(id)
+            .ok_or(err_msg(format!("Cannot find inode of {:?}", &id)))?;
         let drive_id = self
             .get_drive_id(id)
             .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
@@ -452,8 +1300,31 @@ impl FileManager {
             .get_node_id(&FileId::Inode(TRASH_INODE))
             .ok_or(err_msg("Cannot find node_id of Trash dir"))?;
 
+        // Remember the original parent's Drive id so the file can be restored to the same place.
+        let original_parent_node_id = self.tree.get(&node_id)?.parent().cloned();
+        let original_parent_drive_id = original_parent_node_id
+            .clone()
+            .and_then(|parent_node_id| self.get_drive_id(&FileId::NodeId(parent_node_id)));
+        let original_parent_inode =
+            original_parent_node_id.and_then(|parent_node_id| self.get_inode(&FileId::NodeId(parent_node_id)));
+
         self.tree.move_node(&node_id, ToParent(&trash_id))?;
 
+        if let Some(file) = self.files.get_mut(&inode) {
+            file.trashed_parent_id = original_parent_drive_id;
+        }
+
+        if self.rename_identical_files {
+            if let Some(parent_inode) = original_parent_inode {
+                self.recalculate_duplicate_suffixes_for_parent(parent_inode);
+            }
+        }
+
+        if let Some(parent_inode) = original_parent_inode {
+            self.touch_dir(parent_inode);
+        }
+        self.touch_dir(TRASH_INODE);
+
         // File cannot be identified by FileId::ParentAndName now because the parent has changed.
         // Using DriveId instead.
         if also_on_drive {
@@ -466,6 +1337,48 @@ impl FileManager {
         Ok(())
     }
 
+    /// Restores a previously-trashed file to its original parent, both locally and on Drive.
+    /// Inverse of `move_file_to_trash`. Falls back to `ROOT_INODE` if the original parent no
+    /// longer exists.
+    pub fn restore_from_trash(&mut self, id: &FileId) -> Result<(), Error> {
+        let node_id = self
+            .get_node_id(id)
+            .ok_or(err_msg(format!("Cannot find node_id of {:?}", &id)))?;
+        let inode = self
+            .get_inode(id)
+            .ok_or(err_msg(format!("Cannot find inode of {:?}", &id)))?;
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
+
+        let original_parent_drive_id = self
+            .files
+            .get(&inode)
+            .and_then(|file| file.trashed_parent_id.clone());
+
+        let target_node = original_parent_drive_id
+            .as_ref()
+            .and_then(|parent_drive_id| self.get_node_id(&FileId::DriveId(parent_drive_id.clone())))
+            .or_else(|| self.get_node_id(&FileId::Inode(ROOT_INODE)))
+            .ok_or(err_msg("Cannot find a node to restore into, not even ROOT_INODE"))?;
+        let target_inode = self.get_inode(&FileId::NodeId(target_node.clone()));
+
+        self.tree.move_node(&node_id, ToParent(&target_node))?;
+        self.df.restore_from_trash(drive_id)?;
+
+        if let Some(file) = self.files.get_mut(&inode) {
+            file.set_trashed(false)?;
+            file.trashed_parent_id = None;
+        }
+
+        self.touch_dir(TRASH_INODE);
+        if let Some(target_inode) = target_inode {
+            self.touch_dir(target_inode);
+        }
+
+        Ok(())
+    }
+
     /// Whether a file is trashed on Drive.
     pub fn file_is_trashed(&mut self, id: &FileId) -> Result<bool, Error> {
         let file = self
@@ -475,6 +1388,83 @@ impl FileManager {
         Ok(file.is_trashed())
     }
 
+    /// Stars or un-stars a file, both locally and on Drive. Backs the writable
+    /// `user.drive.starred` xattr.
+    pub fn set_starred(&mut self, id: &FileId, starred: bool) -> Result<(), Error> {
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
+
+        self.df.set_starred(drive_id.clone(), starred)?;
+
+        if let Some(file) = self.get_mut_file(&FileId::DriveId(drive_id)) {
+            if let Some(ref mut drive_file) = file.drive_file.as_mut() {
+                drive_file.starred = Some(starred);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists every permission currently granted on `id`'s Drive file. Backs the readable side of
+    /// the `user.gcsf.share` xattr.
+    pub fn list_permissions(&self, id: &FileId) -> Result<Vec<Permission>, Error> {
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
+
+        self.df.list_permissions(&drive_id)
+    }
+
+    /// Grants `role`/`type_` to `email`/`domain` on `id`'s Drive file if it isn't already granted.
+    /// Backs writing the write-only `user.gcsf.share.add` xattr.
+    pub fn add_permission_if_not_exists(
+        &mut self,
+        id: &FileId,
+        email: Option<&str>,
+        domain: Option<&str>,
+        role: &str,
+        type_: &str,
+    ) -> Result<(), Error> {
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
+
+        self.df
+            .add_permission_if_not_exists(&drive_id, email, domain, role, type_)
+    }
+
+    /// Revokes a previously granted permission from `id`'s Drive file.
+    pub fn remove_permission(&mut self, id: &FileId, permission_id: &str) -> Result<(), Error> {
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
+
+        self.df.remove_permission(&drive_id, permission_id)
+    }
+
+    /// Reconciles `id`'s sharing state to exactly `desired`. Backs writing the full
+    /// `user.gcsf.share` xattr.
+    pub fn reconcile_permissions(
+        &mut self,
+        id: &FileId,
+        desired: &[Permission],
+    ) -> Result<(), Error> {
+        let drive_id = self
+            .get_drive_id(id)
+            .ok_or(err_msg(format!("Cannot find drive_id of {:?}", &id)))?;
+
+        self.df.reconcile_permissions(&drive_id, desired)
+    }
+
+    /// Whether `id` currently sits directly under the local Trash directory.
+    fn is_under_trash(&self, id: &FileId) -> bool {
+        self.get_node_id(id)
+            .and_then(|node_id| self.tree.get(&node_id).ok()?.parent().cloned())
+            .and_then(|parent_node_id| self.get_inode(&FileId::NodeId(parent_node_id)))
+            == Some(TRASH_INODE)
+    }
+
     /// Moves/renames a file locally *and* on Drive.
     pub fn rename(
         &mut self,
@@ -489,36 +1479,7 @@ impl FileManager {
                 .ok_or(err_msg(format!("Cannot find node_id of {:?}", &id)))?,
         );
 
-        let current_node = self
-            .get_node_id(&id)
-            .ok_or(err_msg(format!("Cannot find node_id of {:?}", &id)))?;
-        let target_node = self
-            .get_node_id(&FileId::Inode(new_parent))
-            .ok_or(err_msg("Target node doesn't exist"))?;
-
-        self.tree.move_node(&current_node, ToParent(&target_node))?;
-
-        {
-            if self.rename_identical_files {
-                let identical_filename_count = self
-                    .get_children(&FileId::Inode(new_parent))
-                    .ok_or(err_msg("FileManager::rename() could not get file siblings"))?
-                    .iter()
-                    .filter(|child| child.name == new_name)
-                    .count();
-
-               let file = self
-                    .get_mut_file(&id)
-                  .ok_or(err_msg("File doesn't exist"))?;
-               file.name = new_name.clone();
-
-                if identical_filename_count > 0 {
-                    file.identical_name_id = Some(identical_filename_count);
-                } else {
-                    file.identical_name_id = None;
-                }
-            }
-        }
+        self.rename_locally(&id, new_parent, &new_name)?;
 
         let drive_id = self
             .get_drive_id(&id)
@@ -535,6 +1496,213 @@ impl FileManager {
         Ok(())
     }
 
+    /// Moves/renames `id` to `(new_parent, new_name)` in the local tree only, without touching
+    /// Drive. Factored out of `rename` so `exchange` can restage both files' local slots (through
+    /// a scratch name, to dodge a same-name collision) before deciding what to actually tell
+    /// Drive -- Drive itself has no uniqueness constraint on (parent, name), so only the local
+    /// tree ever needs the scratch step.
+    fn rename_locally(&mut self, id: &FileId, new_parent: Inode, new_name: &str) -> Result<(), Error> {
+        let current_node = self
+            .get_node_id(id)
+            .ok_or(err_msg(format!("Cannot find node_id of {:?}", &id)))?;
+        let target_node = self
+            .get_node_id(&FileId::Inode(new_parent))
+            .ok_or(err_msg("Target node doesn't exist"))?;
+
+        self.tree.move_node(&current_node, ToParent(&target_node))?;
+
+        let suffix = if self.rename_identical_files {
+            let new_parent_id = FileId::Inode(new_parent);
+            let has_sibling_with_same_name = self
+                .get_children(&new_parent_id)
+                .ok_or(err_msg("FileManager::rename() could not get file siblings"))?
+                .iter()
+                .filter(|child| child.inode() != self.get_inode(id).unwrap())
+                .any(|child| child.name == new_name);
+
+            if has_sibling_with_same_name {
+                Some(self.smallest_free_suffix(&new_parent_id, new_name))
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        let file = self.get_mut_file(id).ok_or(err_msg("File doesn't exist"))?;
+        file.name = new_name.to_string();
+        file.identical_name_id = suffix;
+
+        Ok(())
+    }
+
+    /// The local-tree half of `exchange`: stages `a` through a scratch name so it never collides
+    /// with `b` in the tree, then settles both into their swapped (parent, name). Returns the
+    /// inodes/parents/final-names `exchange` needs to then move each file on Drive exactly once.
+    ///
+    /// `a` can't be moved directly into `b`'s current (parent, name) locally: that slot is still
+    /// occupied by `b` in our tree, so the move would collide with it as a same-name sibling.
+    /// `a` is staged through a scratch name in the local tree first:
+    ///   1. `a` -> scratch name, still under `a_parent` (local only).
+    ///   2. `b` -> `a`'s old (parent, name), now vacated (local only).
+    ///   3. `a` -> `b`'s old (parent, name), now vacated (local only).
+    /// Drive itself has no such collision (a folder can hold two files of the same name), so the
+    /// scratch step never needs to touch it: once the local tree is settled, `a` and `b` are each
+    /// moved on Drive exactly once, straight to their real final (parent, name).
+    fn exchange_locally(
+        &mut self,
+        a: &FileId,
+        b: &FileId,
+    ) -> Result<(Inode, Inode, Inode, Inode, String, String), Error> {
+        let a_inode = self
+            .get_inode(a)
+            .ok_or(err_msg(format!("Cannot find inode of {:?}", &a)))?;
+        let b_inode = self
+            .get_inode(b)
+            .ok_or(err_msg(format!("Cannot find inode of {:?}", &b)))?;
+
+        let a_parent = self
+            .get_node_id(&FileId::Inode(a_inode))
+            .and_then(|node_id| self.tree.get(&node_id).ok()?.parent().cloned())
+            .and_then(|parent_node_id| self.get_inode(&FileId::NodeId(parent_node_id)))
+            .ok_or(err_msg("Cannot find parent of first file"))?;
+        let b_parent = self
+            .get_node_id(&FileId::Inode(b_inode))
+            .and_then(|node_id| self.tree.get(&node_id).ok()?.parent().cloned())
+            .and_then(|parent_node_id| self.get_inode(&FileId::NodeId(parent_node_id)))
+            .ok_or(err_msg("Cannot find parent of second file"))?;
+
+        let a_name = self
+            .files
+            .get(&a_inode)
+            .ok_or(err_msg("First file disappeared"))?
+            .name
+            .clone();
+        let b_name = self
+            .files
+            .get(&b_inode)
+            .ok_or(err_msg("Second file disappeared"))?
+            .name
+            .clone();
+
+        let scratch_name = format!(".gcsf_exchange_{}_{}", a_inode, b_inode);
+
+        self.rename_locally(&FileId::Inode(a_inode), a_parent, &scratch_name)?;
+        self.rename_locally(&FileId::Inode(b_inode), a_parent, &a_name)?;
+        self.rename_locally(&FileId::Inode(a_inode), b_parent, &b_name)?;
+
+        if self.rename_identical_files {
+            self.recalculate_duplicate_suffixes_for_parent(a_parent);
+            self.recalculate_duplicate_suffixes_for_parent(b_parent);
+        }
+
+        Ok((a_inode, b_inode, a_parent, b_parent, a_name, b_name))
+    }
+
+    /// Swaps `a` and `b` in place: each takes over the other's current (parent, name), both
+    /// locally and on Drive. Used for `RENAME_EXCHANGE`, where userspace atomic-write patterns
+    /// (write to a temp name, then swap it into place) would otherwise have to go through an
+    /// intermediate state that a plain pair of `rename()` calls can't avoid on its own. See
+    /// `exchange_locally` for how the local tree restaging avoids a same-name collision.
+    pub fn exchange(&mut self, a: &FileId, b: &FileId) -> Result<(), Error> {
+        let (a_inode, b_inode, a_parent, b_parent, a_name, b_name) = self.exchange_locally(a, b)?;
+
+        // From here on, the local tree is already restaged to its post-swap shape, so any failure
+        // -- including just failing to resolve one of the drive_ids below, not only a failed
+        // `move_to` -- needs to roll that back rather than return a bare error and leave the local
+        // tree swapped with nothing done (or only half done) on Drive to match.
+        let a_drive_id = match self.get_drive_id(&FileId::Inode(a_inode)) {
+            Some(id) => id,
+            None => return self.undo_exchange_missing_drive_id(a_inode, b_inode, "first file"),
+        };
+        let b_drive_id = match self.get_drive_id(&FileId::Inode(b_inode)) {
+            Some(id) => id,
+            None => return self.undo_exchange_missing_drive_id(a_inode, b_inode, "second file"),
+        };
+        let a_parent_drive_id = match self.get_drive_id(&FileId::Inode(a_parent)) {
+            Some(id) => id,
+            None => {
+                return self.undo_exchange_missing_drive_id(a_inode, b_inode, "first file's parent")
+            }
+        };
+        let b_parent_drive_id = match self.get_drive_id(&FileId::Inode(b_parent)) {
+            Some(id) => id,
+            None => {
+                return self.undo_exchange_missing_drive_id(a_inode, b_inode, "second file's parent")
+            }
+        };
+
+        if let Err(cause) = self.df.move_to(&a_drive_id, &b_parent_drive_id, &b_name) {
+            // Neither move reached Drive yet, so there's nothing to undo there -- just un-restage
+            // the local tree.
+            return self.undo_exchange(a_inode, b_inode, cause);
+        }
+
+        if let Err(cause) = self.df.move_to(&b_drive_id, &a_parent_drive_id, &a_name) {
+            // `a` already landed in `b`'s old slot on Drive, but `b` never made it into `a`'s old
+            // slot. Put `a` back where it came from on Drive too, then swap the local tree back to
+            // match.
+            let drive_rollback = self.df.move_to(&a_drive_id, &a_parent_drive_id, &a_name);
+            let local_rollback = self.restage_locally(a_inode, b_inode);
+
+            return Err(ExchangeConflict {
+                a: a_drive_id,
+                b: b_drive_id,
+                resynced: drive_rollback.is_ok() && local_rollback,
+                cause,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Un-restages the local tree back to how it was before `exchange_locally` ran, by swapping
+    /// `a_inode` and `b_inode` back -- using the already-resolved inodes, not the original `a`/`b`
+    /// `FileId`s `exchange` was called with, since whichever of those was a
+    /// `FileId::ParentAndName` no longer means the same thing once the tree has been swapped.
+    /// Returns whether that put the local tree back in sync.
+    fn restage_locally(&mut self, a_inode: Inode, b_inode: Inode) -> bool {
+        self.exchange_locally(&FileId::Inode(a_inode), &FileId::Inode(b_inode))
+            .is_ok()
+    }
+
+    /// Same as `undo_exchange`, for the specific case of failing to resolve `what`'s drive_id.
+    fn undo_exchange_missing_drive_id(
+        &mut self,
+        a_inode: Inode,
+        b_inode: Inode,
+        what: &str,
+    ) -> Result<(), Error> {
+        self.undo_exchange(a_inode, b_inode, err_msg(format!("Cannot find drive_id of {}", what)))
+    }
+
+    /// Rolls the local tree back via `restage_locally` and reports `cause` -- whatever drive_id
+    /// resolution or `move_to` call `exchange` failed on -- as an `ExchangeConflict`. No Drive-side
+    /// rollback happens here: by the time this is called, either no Drive move has happened yet,
+    /// or (the one case where one did) the caller has already attempted to undo it itself.
+    fn undo_exchange(&mut self, a_inode: Inode, b_inode: Inode, cause: Error) -> Result<(), Error> {
+        let resynced = self.restage_locally(a_inode, b_inode);
+
+        // Falls back to identifying the file by inode if it turns out it doesn't have a drive_id
+        // either (e.g. that's exactly what `cause` is complaining about) -- better than reporting
+        // an empty id.
+        let a_drive_id = self
+            .get_drive_id(&FileId::Inode(a_inode))
+            .unwrap_or_else(|| format!("inode {}", a_inode));
+        let b_drive_id = self
+            .get_drive_id(&FileId::Inode(b_inode))
+            .unwrap_or_else(|| format!("inode {}", b_inode));
+
+        Err(ExchangeConflict {
+            a: a_drive_id,
+            b: b_drive_id,
+            resynced,
+            cause,
+        }
+        .into())
+    }
+
     /// Writes to a file locally *and* on Drive. Note: the pending write is not necessarily applied
     /// instantly by the `DriveFacade`.
     pub fn write(&mut self, id: FileId, offset: usize, data: &[u8]) {
@@ -543,6 +1711,88 @@ impl FileManager {
     }
 }
 
+#[cfg(test)]
+impl FileManager {
+    /// Builds a bare `FileManager` (root node only, no Drive connection) for unit tests of the
+    /// duplicate-suffix logic. Use `add_test_file` to populate the tree by hand instead of going
+    /// through `populate()`.
+    #[allow(unsafe_code)]
+    pub fn new_for_testing(rename_identical_files: bool) -> Self {
+        let mut manager = FileManager {
+            tree: TreeBuilder::new().with_node_capacity(16).build(),
+            files: HashMap::new(),
+            node_ids: HashMap::new(),
+            drive_ids: HashMap::new(),
+            // Never dereferenced: tests never sync with Drive and always `std::mem::forget` the
+            // manager instead of letting it drop.
+            df: unsafe { std::mem::zeroed() },
+            last_sync: SystemTime::now(),
+            sync_interval: Duration::from_secs(0),
+            rename_identical_files,
+            excludes: RegexSet::empty(),
+            includes: RegexSet::empty(),
+            excluded_drive_ids: HashSet::new(),
+            dir_mtimes: HashMap::new(),
+            duplicate_policy: DuplicatePolicy::default(),
+            export_all_formats: false,
+            snapshot_path: None,
+            last_inode: ROOT_INODE,
+        };
+
+        let root_id = manager
+            .tree
+            .insert(Node::new(ROOT_INODE), AsRoot)
+            .expect("inserting the root node of a fresh tree cannot fail");
+        manager.node_ids.insert(ROOT_INODE, root_id);
+
+        manager
+    }
+
+    /// Inserts `file` under `parent_inode` directly into the tree, without communicating with
+    /// Drive or assigning a duplicate suffix. Tests call `recalculate_duplicate_suffixes_for_parent`
+    /// (or `recalculate_all_duplicate_suffixes`) afterwards to exercise that logic explicitly.
+    pub fn add_test_file(&mut self, file: File, parent_inode: Inode) -> Result<(), Error> {
+        let parent_id = self.node_ids.get(&parent_inode).cloned().ok_or_else(|| {
+            err_msg(format!(
+                "add_test_file: unknown parent inode {}",
+                parent_inode
+            ))
+        })?;
+
+        let node_id = self
+            .tree
+            .insert(Node::new(file.inode()), UnderNode(&parent_id))?;
+        self.node_ids.insert(file.inode(), node_id);
+
+        if let Some(drive_id) = file.drive_id() {
+            self.drive_ids.insert(drive_id, file.inode());
+        }
+        self.files.insert(file.inode(), file);
+
+        Ok(())
+    }
+
+    /// Test-only window onto `duplicates_to_trash`, so the keep-newest/keep-oldest selection can
+    /// be unit-tested without going through `apply_duplicate_policy` (which trashes the losers on
+    /// Drive, and so can't be run against the Drive-less `FileManager` these tests build).
+    pub fn duplicates_to_trash_for_testing(
+        policy: DuplicatePolicy,
+        live_oldest_first: &[Inode],
+    ) -> Vec<Inode> {
+        Self::duplicates_to_trash(policy, live_oldest_first)
+    }
+
+    /// Test-only window onto `exchange_locally`, so `exchange`'s actual local tree restaging
+    /// (not a hand-replayed copy of it) can be unit-tested without touching Drive.
+    pub fn exchange_locally_for_testing(
+        &mut self,
+        a: &FileId,
+        b: &FileId,
+    ) -> Result<(Inode, Inode, Inode, Inode, String, String), Error> {
+        self.exchange_locally(a, b)
+    }
+}
+
 impl fmt::Debug for FileManager {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "FileManager(\n")?;
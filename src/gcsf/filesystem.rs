@@ -1,16 +1,25 @@
-use super::{Config, File, FileId, FileManager};
+use super::{
+    Config, ExchangeConflict, File, FileId, FileManager, FlushConflict, Permission,
+    SHORTCUT_MIME_TYPE,
+};
 use crate::DriveFacade;
 use drive3;
 use failure::Error;
+use serde_json;
 use fuser::{
     FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
-    ReplyEntry, ReplyStatfs, ReplyWrite, Request,
+    ReplyEntry, ReplyLock, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request,
+};
+use libc::{
+    EACCES, EAGAIN, EEXIST, ENODATA, ENOENT, ENOTDIR, ENOTRECOVERABLE, ENOTSUP, EREMOTE, EROFS,
+    O_ACCMODE, O_APPEND, O_RDWR, O_TRUNC, O_WRONLY, RENAME_EXCHANGE, RENAME_NOREPLACE, R_OK,
+    W_OK, X_OK,
 };
-use libc::{ENOENT, ENOTDIR, ENOTRECOVERABLE, EREMOTE, EROFS};
 use lru_time_cache::LruCache;
 use std;
 use std::clone::Clone;
 use std::cmp;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 
 pub type Inode = u64;
@@ -63,11 +72,264 @@ macro_rules! reject_if_readonly {
 pub struct NullFs;
 impl Filesystem for NullFs {}
 
+/// Standard Unix access check: does `req_uid`/`req_gid` (plus `req_uid`'s supplementary groups)
+/// satisfy `mask` (some combination of `R_OK`/`W_OK`/`X_OK`) against `file`'s `perm`/`uid`/`gid`?
+/// Root is always allowed, except that execute still requires at least one `x` bit to be set
+/// (mirrors the kernel's own treatment of `CAP_DAC_OVERRIDE`).
+fn check_access(file: &File, req_uid: u32, req_gid: u32, mask: i32) -> bool {
+    let perm = file.attr.perm as i32;
+
+    if req_uid == 0 {
+        return mask & X_OK == 0 || perm & 0o111 != 0;
+    }
+
+    let in_group = req_gid == file.attr.gid
+        || users::get_user_by_uid(req_uid)
+            .and_then(|user| users::get_user_groups(user.name(), req_gid))
+            .map(|groups| groups.iter().any(|g| g.gid() == file.attr.gid))
+            .unwrap_or(false);
+
+    let triad = if req_uid == file.attr.uid {
+        perm >> 6
+    } else if in_group {
+        perm >> 3
+    } else {
+        perm
+    };
+
+    mask & !triad & 0o7 == 0
+}
+
+/// Test-only window onto `check_access`, so the owner/group/other permission-triad selection can
+/// be unit-tested directly rather than through a live FUSE `access`/`read`/`write` call.
+#[cfg(test)]
+pub fn check_access_for_testing(file: &File, req_uid: u32, req_gid: u32, mask: i32) -> bool {
+    check_access(file, req_uid, req_gid, mask)
+}
+
+/// Per-`open()` bookkeeping for a file handle, so `read`/`write`/`flush`/`release` don't have to
+/// re-derive O_ACCMODE/O_APPEND from the inode alone. Keyed by `fh` on `Gcsf::open_handles`.
+struct HandleState {
+    inode: Inode,
+    writable: bool,
+    append: bool,
+    dirty: bool,
+}
+
+/// One POSIX byte-range lock (as tracked by `getlk`/`setlk`), covering `[start, end]` of an
+/// inode's body for a single `lock_owner`.
+#[derive(Clone, Copy)]
+struct LockRange {
+    start: u64,
+    end: u64,
+    typ: i32,
+    lock_owner: u64,
+    pid: u32,
+}
+
+fn ranges_overlap(a_start: u64, a_end: u64, b_start: u64, b_end: u64) -> bool {
+    a_start <= b_end && b_start <= a_end
+}
+
+/// The first range conflicting with a would-be lock of `[start, end]`/`typ` held by `owner`: a
+/// write lock conflicts with any overlapping range from a different owner, a read lock conflicts
+/// only with an overlapping write range from a different owner.
+fn find_lock_conflict(
+    ranges: &[LockRange],
+    owner: u64,
+    start: u64,
+    end: u64,
+    typ: i32,
+) -> Option<LockRange> {
+    ranges
+        .iter()
+        .find(|lock| {
+            lock.lock_owner != owner
+                && ranges_overlap(lock.start, lock.end, start, end)
+                && (typ == libc::F_WRLCK || lock.typ == libc::F_WRLCK)
+        })
+        .copied()
+}
+
+/// Records a newly (conflict-free) acquired lock for `owner`, merging it with any of that
+/// owner's existing ranges that are now overlapping or touching and of the same type, and
+/// dropping any differently-typed range of the owner's that ends up fully covered by it (e.g.
+/// upgrading a read lock to a write lock over the same bytes).
+fn acquire_lock_range(ranges: &mut Vec<LockRange>, owner: u64, start: u64, end: u64, typ: i32, pid: u32) {
+    let mut merged_start = start;
+    let mut merged_end = end;
+
+    ranges.retain(|lock| {
+        if lock.lock_owner != owner || lock.typ != typ {
+            return true;
+        }
+
+        let touching =
+            lock.start <= merged_end.saturating_add(1) && merged_start <= lock.end.saturating_add(1);
+        if touching {
+            merged_start = cmp::min(merged_start, lock.start);
+            merged_end = cmp::max(merged_end, lock.end);
+        }
+
+        !touching
+    });
+
+    ranges.retain(|lock| {
+        lock.lock_owner != owner
+            || lock.typ == typ
+            || !(merged_start <= lock.start && lock.end <= merged_end)
+    });
+
+    ranges.push(LockRange {
+        start: merged_start,
+        end: merged_end,
+        typ,
+        lock_owner: owner,
+        pid,
+    });
+}
+
+/// Drops every lock range `owner` holds on this inode outright, regardless of range. Used by
+/// `release()`: POSIX drops all of a process's locks on a file the moment any of its file
+/// descriptors for that file closes, not just a specifically-requested sub-range (that's
+/// `release_lock_range`, for an explicit `setlk(F_UNLCK)`).
+fn release_lock_owner(ranges: &mut Vec<LockRange>, owner: u64) {
+    ranges.retain(|lock| lock.lock_owner != owner);
+}
+
+/// Clears `[start, end]` of `owner`'s locks on this inode, symmetric to how `acquire_lock_range`
+/// merges a newly-acquired range in: an owner's range entirely inside `[start, end]` is dropped,
+/// one only partially overlapping is trimmed down to what's left outside it, and one that strictly
+/// contains `[start, end]` is split in two around the now-unlocked middle. Ranges belonging to
+/// other owners, or not overlapping `[start, end]` at all, are untouched.
+fn release_lock_range(ranges: &mut Vec<LockRange>, owner: u64, start: u64, end: u64) {
+    let mut remaining = Vec::with_capacity(ranges.len());
+
+    for lock in ranges.drain(..) {
+        if lock.lock_owner != owner || !ranges_overlap(lock.start, lock.end, start, end) {
+            remaining.push(lock);
+            continue;
+        }
+
+        if lock.start < start {
+            remaining.push(LockRange {
+                start: lock.start,
+                end: start - 1,
+                ..lock
+            });
+        }
+
+        if end < lock.end {
+            remaining.push(LockRange {
+                start: end + 1,
+                end: lock.end,
+                ..lock
+            });
+        }
+    }
+
+    *ranges = remaining;
+}
+
+/// Test-only window onto the lock-range bookkeeping, so conflict detection and merge-on-acquire
+/// can be unit-tested without standing up a FUSE `getlk`/`setlk` call. Ranges are exposed as
+/// plain `(start, end, typ, lock_owner, pid)` tuples rather than `LockRange` itself, since that
+/// struct's fields are private to this module.
+#[cfg(test)]
+pub fn acquire_lock_range_for_testing(
+    ranges: &mut Vec<(u64, u64, i32, u64, u32)>,
+    owner: u64,
+    start: u64,
+    end: u64,
+    typ: i32,
+    pid: u32,
+) {
+    let mut lock_ranges: Vec<LockRange> = ranges
+        .iter()
+        .map(|&(start, end, typ, lock_owner, pid)| LockRange {
+            start,
+            end,
+            typ,
+            lock_owner,
+            pid,
+        })
+        .collect();
+
+    acquire_lock_range(&mut lock_ranges, owner, start, end, typ, pid);
+
+    *ranges = lock_ranges
+        .into_iter()
+        .map(|lock| (lock.start, lock.end, lock.typ, lock.lock_owner, lock.pid))
+        .collect();
+}
+
+#[cfg(test)]
+pub fn find_lock_conflict_for_testing(
+    ranges: &[(u64, u64, i32, u64, u32)],
+    owner: u64,
+    start: u64,
+    end: u64,
+    typ: i32,
+) -> bool {
+    let lock_ranges: Vec<LockRange> = ranges
+        .iter()
+        .map(|&(start, end, typ, lock_owner, pid)| LockRange {
+            start,
+            end,
+            typ,
+            lock_owner,
+            pid,
+        })
+        .collect();
+
+    find_lock_conflict(&lock_ranges, owner, start, end, typ).is_some()
+}
+
+#[cfg(test)]
+pub fn release_lock_range_for_testing(
+    ranges: &mut Vec<(u64, u64, i32, u64, u32)>,
+    owner: u64,
+    start: u64,
+    end: u64,
+) {
+    let mut lock_ranges: Vec<LockRange> = ranges
+        .iter()
+        .map(|&(start, end, typ, lock_owner, pid)| LockRange {
+            start,
+            end,
+            typ,
+            lock_owner,
+            pid,
+        })
+        .collect();
+
+    release_lock_range(&mut lock_ranges, owner, start, end);
+
+    *ranges = lock_ranges
+        .into_iter()
+        .map(|lock| (lock.start, lock.end, lock.typ, lock.lock_owner, lock.pid))
+        .collect();
+}
+
 /// A FUSE file system which is linked to a Google Drive account.
 pub struct Gcsf {
     manager: FileManager,
     statfs_cache: LruCache<String, u64>,
     read_only: bool,
+
+    /// Open file handles, keyed by the `fh` handed out from `open()`.
+    open_handles: HashMap<u64, HandleState>,
+
+    /// Monotonically increasing counter used to hand out the next `fh` from `open()`.
+    next_fh: u64,
+
+    /// Active POSIX byte-range locks (see `getlk`/`setlk`), keyed by inode.
+    locks: HashMap<Inode, Vec<LockRange>>,
+
+    /// The `Config` this mount was created with. Kept around so `reload()` (triggered by the
+    /// `user.gcsf.reload` xattr, see `setxattr`) can re-authenticate against it -- e.g. pick up a
+    /// rotated OAuth token from `config.token_file()` -- without a remount.
+    config: Config,
 }
 
 const TTL: std::time::Duration = std::time::Duration::from_secs(1);
@@ -76,26 +338,85 @@ impl Gcsf {
     /// Constructs a Gcsf instance using a given Config.
     pub fn with_config(config: Config) -> Result<Self, Error> {
         Ok(Gcsf {
-            manager: FileManager::with_drive_facade(
+            manager: FileManager::with_drive_facade_and_snapshot(
                 config.rename_identical_files(),
-                config.add_extensions_to_special_files(),
-                config.skip_trash(),
                 config.sync_interval(),
                 DriveFacade::new(&config),
+                config.excludes(),
+                config.includes(),
+                config.export_all_formats(),
+                Some(&config.snapshot_path()),
             )?,
             statfs_cache: LruCache::<String, u64>::with_expiry_duration_and_capacity(
                 config.cache_statfs_seconds(),
                 2,
             ),
             read_only: config.read_only(),
+            open_handles: HashMap::new(),
+            next_fh: 1,
+            locks: HashMap::new(),
+            config,
         })
     }
+
+    /// Re-authenticates the `DriveFacade` (picking up a rotated OAuth token or changed client
+    /// secret) and forces a full `FileManager` re-scan against Drive, the way a remount would --
+    /// without actually unmounting. Triggered by the `user.gcsf.reload` xattr (see `setxattr`).
+    /// Existing inodes are replaced, so a file handle opened before the reload may fail on its
+    /// next operation; that's the one user-visible cost, same as it would be across a remount.
+    fn reload(&mut self) -> Result<(), Error> {
+        info!("Reloading: re-authenticating and re-scanning Drive");
+        let df = DriveFacade::new(&self.config);
+        self.manager.reload(df)
+    }
+
+    /// Grants access to the underlying `FileManager` without going through a FUSE mount, the way
+    /// `main`'s `gcsf verify` command needs to walk the tree and read file content directly.
+    pub fn manager_mut(&mut self) -> &mut FileManager {
+        &mut self.manager
+    }
+
+    /// Used bytes and total capacity of the underlying Drive account, refreshing from
+    /// `DriveFacade::size_and_capacity` no more often than `statfs_cache`'s expiry allows. Shared
+    /// by `statfs` and by `main`'s union-mount aggregation across several accounts.
+    pub fn size_and_capacity(&mut self) -> (u64, u64) {
+        if !self.statfs_cache.contains_key("size") || !self.statfs_cache.contains_key("capacity") {
+            let (size, capacity) = self.manager.df.size_and_capacity().unwrap_or((0, Some(0)));
+            let capacity = capacity.unwrap_or(i64::MAX as u64);
+            self.statfs_cache.insert("size".to_string(), size);
+            self.statfs_cache.insert("capacity".to_string(), capacity);
+
+            (size, capacity)
+        } else {
+            // unwrap_or(&0) because the values might have been dropped from the cache since
+            // checking for their existence.
+            let size = self.statfs_cache.get("size").unwrap_or(&0).to_owned();
+            let capacity = self.statfs_cache.get("capacity").unwrap_or(&0).to_owned();
+            (size, capacity)
+        }
+    }
+}
+
+impl Drop for Gcsf {
+    /// Persists the snapshot one last time on unmount (including a clean shutdown from a
+    /// SIGTERM/Ctrl-C handler, see `main.rs`), so the next mount resumes from the latest state
+    /// rather than the last periodic save made during `sync()`.
+    fn drop(&mut self) {
+        self.manager.save_snapshot_if_configured();
+    }
 }
 
 impl Filesystem for Gcsf {
-    fn lookup(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEntry) {
+    fn lookup(&mut self, req: &Request, parent: Inode, name: &OsStr, reply: ReplyEntry) {
         // self.manager.sync();
 
+        if let Some(dir) = self.manager.get_file(&FileId::Inode(parent)) {
+            if !check_access(dir, req.uid(), req.gid(), X_OK) {
+                reply.error(EACCES);
+                return;
+            }
+        }
+
         let name = name.to_str().unwrap().to_string();
         let id = FileId::ParentAndName { parent, name };
 
@@ -109,6 +430,19 @@ impl Filesystem for Gcsf {
         };
     }
 
+    fn access(&mut self, req: &Request, ino: Inode, mask: i32, reply: ReplyEmpty) {
+        match self.manager.get_file(&FileId::Inode(ino)) {
+            Some(file) => {
+                if check_access(file, req.uid(), req.gid(), mask) {
+                    reply.ok();
+                } else {
+                    reply.error(EACCES);
+                }
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
     fn getattr(&mut self, _req: &Request, ino: Inode, _fh: Option<u64>, reply: ReplyAttr) {
         // self.manager.sync();
         match self.manager.get_file(&FileId::Inode(ino)) {
@@ -121,23 +455,118 @@ impl Filesystem for Gcsf {
         };
     }
 
-    fn read(
+    fn open(&mut self, _req: &Request, ino: Inode, flags: i32, reply: ReplyOpen) {
+        if !self.manager.contains(&FileId::Inode(ino)) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let writable = matches!(flags & O_ACCMODE, O_WRONLY | O_RDWR);
+        if writable && self.read_only {
+            warn!("Rejecting open() for writing: filesystem is read-only");
+            reply.error(EROFS);
+            return;
+        }
+
+        if writable && flags & O_TRUNC != 0 {
+            if let Err(e) = self.manager.truncate(&FileId::Inode(ino)) {
+                error!("open: could not truncate inode={}: {}", ino, e);
+                reply.error(EREMOTE);
+                return;
+            }
+        }
+
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open_handles.insert(
+            fh,
+            HandleState {
+                inode: ino,
+                writable,
+                append: flags & O_APPEND != 0,
+                dirty: false,
+            },
+        );
+
+        reply.opened(fh, 0);
+    }
+
+    fn release(
         &mut self,
         _req: &Request,
         ino: Inode,
-        _fh: u64,
+        fh: u64,
+        _flags: i32,
+        lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        if let Some(handle) = self.open_handles.remove(&fh) {
+            let still_has_writer = self
+                .open_handles
+                .values()
+                .any(|h| h.inode == handle.inode && h.writable);
+
+            if handle.dirty && still_has_writer {
+                // Hand the pending push over to whichever writable handle closes last.
+                for other in self
+                    .open_handles
+                    .values_mut()
+                    .filter(|h| h.inode == handle.inode && h.writable)
+                {
+                    other.dirty = true;
+                }
+            }
+
+            if handle.writable && handle.dirty && !still_has_writer {
+                if let Err(e) = self.manager.flush(&FileId::Inode(handle.inode)) {
+                    if e.downcast_ref::<FlushConflict>().is_some() {
+                        error!(
+                            "release: flush for inode={} hit an unresolved remote conflict: {}",
+                            handle.inode, e
+                        );
+                        reply.error(EAGAIN);
+                        return;
+                    }
+                    error!("release: could not flush inode={}: {}", handle.inode, e);
+                }
+            }
+        }
+
+        if let Some(owner) = lock_owner {
+            if let Some(ranges) = self.locks.get_mut(&ino) {
+                release_lock_owner(ranges, owner);
+            }
+        }
+
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: Inode,
+        fh: u64,
         offset: i64,
         size: u32,
         _flags: i32,
         _lock_owner: Option<u64>,
         reply: ReplyData,
     ) {
-        if !self.manager.contains(&FileId::Inode(ino)) {
-            reply.error(ENOENT);
+        let file = match self.manager.get_file(&FileId::Inode(ino)) {
+            Some(file) => file,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if !check_access(file, req.uid(), req.gid(), R_OK) {
+            reply.error(EACCES);
             return;
         }
+        debug!("read(ino={}, fh={}, offset={}, size={})", ino, fh, offset, size);
 
-        let (mime, id) = self
+        let (mime, export_mime, id) = self
             .manager
             .get_file(&FileId::Inode(ino))
             .map(|f| {
@@ -148,23 +577,23 @@ impl Filesystem for Gcsf {
                     .cloned();
                 let id = f.drive_id().unwrap();
 
-                (mime, id)
+                (mime, f.export_mime_type.clone(), id)
             })
             .unwrap();
 
         reply.data(
             self.manager
                 .df
-                .read(&id, mime, offset as usize, size as usize)
+                .read(&id, mime, export_mime, offset as usize, size as usize)
                 .unwrap_or(&[]),
         );
     }
 
     fn write(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: Inode,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         data: &[u8],
         _write_flags: u32,
@@ -173,8 +602,38 @@ impl Filesystem for Gcsf {
         reply: ReplyWrite,
     ) {
         reject_if_readonly!(self, reply);
-        let offset: usize = cmp::max(offset, 0) as usize;
+
+        match self.manager.get_file(&FileId::Inode(ino)) {
+            Some(file) if check_access(file, req.uid(), req.gid(), W_OK) => {}
+            Some(_) => {
+                reply.error(EACCES);
+                return;
+            }
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        }
+
+        let append = self
+            .open_handles
+            .get(&fh)
+            .map(|handle| handle.append)
+            .unwrap_or(false);
+
+        let offset: usize = if append {
+            self.manager
+                .get_file(&FileId::Inode(ino))
+                .map(|file| file.attr.size)
+                .unwrap_or(0) as usize
+        } else {
+            cmp::max(offset, 0) as usize
+        };
+
         self.manager.write(FileId::Inode(ino), offset, data);
+        if let Some(handle) = self.open_handles.get_mut(&fh) {
+            handle.dirty = true;
+        }
 
         match self.manager.get_mut_file(&FileId::Inode(ino)) {
             Some(ref mut file) => {
@@ -195,8 +654,10 @@ impl Filesystem for Gcsf {
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
-        if let Err(e) = self.manager.sync() {
-            debug!("Could not perform sync: {}", e);
+        if self.manager.dir_is_stale(&FileId::Inode(ino), self.manager.sync_interval) {
+            if let Err(e) = self.manager.reconcile_dir(&FileId::Inode(ino)) {
+                debug!("Could not reconcile directory {}: {}", ino, e);
+            }
         }
         // println!("current state: {:#?}", self.manager);
 
@@ -225,7 +686,7 @@ impl Filesystem for Gcsf {
         name: &OsStr,
         newparent: Inode,
         newname: &OsStr,
-        _flags: u32,
+        flags: u32,
         reply: ReplyEmpty,
     ) {
         reject_if_readonly!(self, reply);
@@ -237,6 +698,41 @@ impl Filesystem for Gcsf {
                 .get_inode(&FileId::ParentAndName { parent, name })
                 .unwrap_or(0),
         );
+        let target_id = FileId::ParentAndName {
+            parent: newparent,
+            name: newname.clone(),
+        };
+        let target_exists = self.manager.contains(&target_id);
+
+        if flags & (RENAME_EXCHANGE as u32) != 0 {
+            if !target_exists {
+                reply.error(ENOENT);
+                return;
+            }
+
+            if let Err(e) = self.manager.exchange(&id, &target_id) {
+                if let Some(conflict) = e.downcast_ref::<ExchangeConflict>() {
+                    if conflict.resynced {
+                        error!("rename: exchange hit a remote conflict, rolled back cleanly: {}", e);
+                        reply.error(EAGAIN);
+                    } else {
+                        error!("rename: exchange hit a remote conflict and failed to roll back: {}", e);
+                        reply.error(EREMOTE);
+                    }
+                    return;
+                }
+                error!("rename: exchange failed: {}", e);
+                reply.error(ENOTRECOVERABLE);
+                return;
+            }
+            reply.ok();
+            return;
+        }
+
+        if flags & (RENAME_NOREPLACE as u32) != 0 && target_exists {
+            reply.error(EEXIST);
+            return;
+        }
 
         if newparent == TRASH_INODE {
             let rename_res = self.manager.rename(&id, parent, newname);
@@ -315,22 +811,29 @@ impl Filesystem for Gcsf {
         req: &Request,
         parent: Inode,
         name: &OsStr,
-        _mode: u32,
-        _umask: u32,
-        _flags: i32,
+        mode: u32,
+        umask: u32,
+        flags: i32,
         reply: ReplyCreate,
     ) {
         reject_if_readonly!(self, reply);
         let filename = name.to_str().unwrap().to_string();
 
         // TODO: these two checks might not be necessary
-        if !self.manager.contains(&FileId::Inode(parent)) {
-            error!(
-                "create: could not find parent inode={} in the file tree",
-                parent
-            );
-            reply.error(ENOTDIR);
-            return;
+        match self.manager.get_file(&FileId::Inode(parent)) {
+            Some(dir) if check_access(dir, req.uid(), req.gid(), W_OK | X_OK) => {}
+            Some(_) => {
+                reply.error(EACCES);
+                return;
+            }
+            None => {
+                error!(
+                    "create: could not find parent inode={} in the file tree",
+                    parent
+                );
+                reply.error(ENOTDIR);
+                return;
+            }
         }
         if self.manager.contains(&FileId::ParentAndName {
             parent,
@@ -356,7 +859,7 @@ impl Filesystem for Gcsf {
                 mtime: std::time::SystemTime::now(),
                 ctime: std::time::SystemTime::now(),
                 crtime: std::time::SystemTime::now(),
-                perm: 0o744,
+                perm: (mode & !umask) as u16,
                 nlink: 0,
                 uid: req.uid(),
                 gid: req.gid(),
@@ -372,12 +875,25 @@ impl Filesystem for Gcsf {
                 ]),
                 ..Default::default()
             }),
+            trashed_parent_id: None,
+            symlink_target: None,
         };
 
         let attr = file.attr;
         match self.manager.create_file(file, Some(FileId::Inode(parent))) {
             Ok(()) => {
-                reply.created(&TTL, &attr, 0, 0, 0);
+                let fh = self.next_fh;
+                self.next_fh += 1;
+                self.open_handles.insert(
+                    fh,
+                    HandleState {
+                        inode: attr.ino,
+                        writable: matches!(flags & O_ACCMODE, O_WRONLY | O_RDWR),
+                        append: flags & O_APPEND != 0,
+                        dirty: false,
+                    },
+                );
+                reply.created(&TTL, &attr, 0, fh, 0);
             }
             Err(e) => {
                 error!("create: {}", e);
@@ -386,8 +902,21 @@ impl Filesystem for Gcsf {
         }
     }
 
-    fn unlink(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+    fn unlink(&mut self, req: &Request, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
         reject_if_readonly!(self, reply);
+
+        match self.manager.get_file(&FileId::Inode(parent)) {
+            Some(dir) if check_access(dir, req.uid(), req.gid(), W_OK | X_OK) => {}
+            Some(_) => {
+                reply.error(EACCES);
+                return;
+            }
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        }
+
         let id = FileId::ParentAndName {
             parent,
             name: name.to_str().unwrap().to_string(),
@@ -431,24 +960,31 @@ impl Filesystem for Gcsf {
 
     fn mkdir(
         &mut self,
-        _req: &Request,
+        req: &Request,
         parent: Inode,
         name: &OsStr,
-        _mode: u32,
-        _umask: u32,
+        mode: u32,
+        umask: u32,
         reply: ReplyEntry,
     ) {
         reject_if_readonly!(self, reply);
         let dirname = name.to_str().unwrap().to_string();
 
         // TODO: these two checks might not be necessary
-        if !self.manager.contains(&FileId::Inode(parent)) {
-            error!(
-                "mkdir: could not find parent inode={} in the file tree",
-                parent
-            );
-            reply.error(ENOTDIR);
-            return;
+        match self.manager.get_file(&FileId::Inode(parent)) {
+            Some(dir) if check_access(dir, req.uid(), req.gid(), W_OK | X_OK) => {}
+            Some(_) => {
+                reply.error(EACCES);
+                return;
+            }
+            None => {
+                error!(
+                    "mkdir: could not find parent inode={} in the file tree",
+                    parent
+                );
+                reply.error(ENOTDIR);
+                return;
+            }
         }
         if self.manager.contains(&FileId::ParentAndName {
             parent,
@@ -474,10 +1010,10 @@ impl Filesystem for Gcsf {
                 ctime: std::time::SystemTime::now(),
                 crtime: std::time::SystemTime::now(),
                 blksize: 512,
-                perm: 0o644,
+                perm: (mode & !umask) as u16,
                 nlink: 0,
-                uid: 0,
-                gid: 0,
+                uid: req.uid(),
+                gid: req.gid(),
                 rdev: 0,
                 flags: 0,
             },
@@ -490,6 +1026,8 @@ impl Filesystem for Gcsf {
                 ]),
                 ..Default::default()
             }),
+            trashed_parent_id: None,
+            symlink_target: None,
         };
 
         let attr = dir.attr;
@@ -504,43 +1042,118 @@ impl Filesystem for Gcsf {
         }
     }
 
-    fn rmdir(&mut self, _req: &Request, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+    fn symlink(
+        &mut self,
+        req: &Request,
+        parent: Inode,
+        link_name: &OsStr,
+        target: &std::path::Path,
+        reply: ReplyEntry,
+    ) {
         reject_if_readonly!(self, reply);
-        self.unlink(_req, parent, name, reply);
-    }
+        let filename = link_name.to_str().unwrap().to_string();
+        let target = target.to_str().unwrap().to_string();
 
-    fn flush(&mut self, _req: &Request, ino: Inode, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
-        if self.read_only {
-            // In read-only mode, there are no pending writes, so flush is a no-op
-            reply.ok();
+        if !self.manager.contains(&FileId::Inode(parent)) {
+            error!(
+                "symlink: could not find parent inode={} in the file tree",
+                parent
+            );
+            reply.error(ENOTDIR);
             return;
         }
-        match self.manager.flush(&FileId::Inode(ino)) {
-            Ok(()) => reply.ok(),
+        if self.manager.contains(&FileId::ParentAndName {
+            parent,
+            name: filename.clone(),
+        }) {
+            error!(
+                "symlink: file {:?} of parent(inode={}) already exists",
+                link_name, parent
+            );
+            reply.error(EEXIST);
+            return;
+        }
+
+        let file = File {
+            name: filename.clone(),
+            attr: FileAttr {
+                ino: self.manager.next_available_inode(),
+                kind: FileType::Symlink,
+                size: target.len() as u64,
+                blocks: 1,
+                blksize: 512,
+                atime: std::time::SystemTime::now(),
+                mtime: std::time::SystemTime::now(),
+                ctime: std::time::SystemTime::now(),
+                crtime: std::time::SystemTime::now(),
+                perm: 0o777,
+                nlink: 1,
+                uid: req.uid(),
+                gid: req.gid(),
+                rdev: 0,
+                flags: 0,
+            },
+            identical_name_id: None,
+            drive_file: Some(drive3::api::File {
+                name: Some(filename),
+                mime_type: Some(SHORTCUT_MIME_TYPE.to_string()),
+                // There's no dedicated shortcut-target field available here, so the link text
+                // rides along in `description`, mirroring `symlink_target` (see `File`'s doc
+                // comment).
+                description: Some(target.clone()),
+                parents: Some(vec![
+                    self.manager.get_drive_id(&FileId::Inode(parent)).unwrap(),
+                ]),
+                ..Default::default()
+            }),
+            trashed_parent_id: None,
+            symlink_target: Some(target),
+        };
+
+        let attr = file.attr;
+        match self.manager.create_file(file, Some(FileId::Inode(parent))) {
+            Ok(()) => {
+                reply.entry(&TTL, &attr, 0);
+            }
             Err(e) => {
-                error!("{:?}", e);
+                error!("symlink: {}", e);
                 reply.error(EREMOTE);
             }
         }
     }
 
-    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
-        let (size, capacity) = if !self.statfs_cache.contains_key("size")
-            || !self.statfs_cache.contains_key("capacity")
+    fn readlink(&mut self, _req: &Request, ino: Inode, reply: ReplyData) {
+        match self
+            .manager
+            .get_file(&FileId::Inode(ino))
+            .filter(|file| file.attr.kind == FileType::Symlink)
+            .and_then(|file| file.symlink_target.as_ref())
         {
-            let (size, capacity) = self.manager.df.size_and_capacity().unwrap_or((0, Some(0)));
-            let capacity = capacity.unwrap_or(i64::MAX as u64);
-            self.statfs_cache.insert("size".to_string(), size);
-            self.statfs_cache.insert("capacity".to_string(), capacity);
+            Some(target) => reply.data(target.as_bytes()),
+            None => reply.error(ENOENT),
+        }
+    }
 
-            (size, capacity)
-        } else {
-            // unwrap_or(&0) because the values might have been dropped from the cache since
-            // checking for their existence.
-            let size = self.statfs_cache.get("size").unwrap_or(&0).to_owned();
-            let capacity = self.statfs_cache.get("capacity").unwrap_or(&0).to_owned();
-            (size, capacity)
-        };
+    fn rmdir(&mut self, req: &Request, parent: Inode, name: &OsStr, reply: ReplyEmpty) {
+        reject_if_readonly!(self, reply);
+        self.unlink(req, parent, name, reply);
+    }
+
+    fn flush(&mut self, _req: &Request, _ino: Inode, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        // `flush` fires on every close(2), including duplicated file descriptors, so it would
+        // push the same pending writes to Drive redundantly. The actual push now happens once,
+        // in `release()` of the last writable handle for this inode.
+        if let Some(handle) = self.open_handles.get(&fh) {
+            debug!(
+                "flush(fh={}): dirty={}, deferring the Drive push to release()",
+                fh, handle.dirty
+            );
+        }
+        reply.ok();
+    }
+
+    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        let (size, capacity) = self.size_and_capacity();
 
         let bsize: u32 = 512;
         let blocks: u64 =
@@ -558,4 +1171,316 @@ impl Filesystem for Gcsf {
             /* frsize: */ bsize,
         );
     }
+
+    /// Surfaces Drive metadata (id, mime type, MD5, owners, shared/trashed/starred flags, web
+    /// view link) under the `user.drive.*` namespace, so `getfattr -d`/`getxattr` can read it
+    /// without any custom tooling. `user.gcsf.share` is handled separately since, unlike the rest,
+    /// it requires a live `permissions.list` call instead of just reading cached file state.
+    fn getxattr(&mut self, _req: &Request, ino: Inode, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let name = name.to_string_lossy();
+
+        if name == "user.gcsf.share" {
+            return self.getxattr_share(ino, size, reply);
+        }
+
+        let file = match self.manager.get_file(&FileId::Inode(ino)) {
+            Some(file) => file,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let value = file
+            .drive_xattrs()
+            .into_iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value);
+
+        match value {
+            Some(value) => {
+                let bytes = value.as_bytes();
+                if size == 0 {
+                    reply.size(bytes.len() as u32);
+                } else if bytes.len() > size as usize {
+                    reply.error(libc::ERANGE);
+                } else {
+                    reply.data(bytes);
+                }
+            }
+            None => reply.error(ENODATA),
+        }
+    }
+
+    /// Handles `getxattr(2)` on `user.gcsf.share`: a JSON array of every permission currently
+    /// granted on `ino`'s Drive file, in the same shape `setxattr` expects back.
+    fn getxattr_share(&mut self, ino: Inode, size: u32, reply: ReplyXattr) {
+        let permissions = match self.manager.list_permissions(&FileId::Inode(ino)) {
+            Ok(permissions) => permissions,
+            Err(e) => {
+                error!("getxattr(user.gcsf.share): {}", e);
+                reply.error(EREMOTE);
+                return;
+            }
+        };
+
+        let bytes = match serde_json::to_vec(&permissions) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("getxattr(user.gcsf.share): {}", e);
+                reply.error(ENOTRECOVERABLE);
+                return;
+            }
+        };
+
+        if size == 0 {
+            reply.size(bytes.len() as u32);
+        } else if bytes.len() > size as usize {
+            reply.error(libc::ERANGE);
+        } else {
+            reply.data(&bytes);
+        }
+    }
+
+    /// Enumerates the populated `user.drive.*` keys for `ino`.
+    fn listxattr(&mut self, _req: &Request, ino: Inode, size: u32, reply: ReplyXattr) {
+        let file = match self.manager.get_file(&FileId::Inode(ino)) {
+            Some(file) => file,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let mut names = Vec::new();
+        for (key, _) in file.drive_xattrs() {
+            names.extend_from_slice(key.as_bytes());
+            names.push(0);
+        }
+
+        if size == 0 {
+            reply.size(names.len() as u32);
+        } else if names.len() > size as usize {
+            reply.error(libc::ERANGE);
+        } else {
+            reply.data(&names);
+        }
+    }
+
+    /// `user.drive.starred` is writable with a literal `true`/`false` value. `user.gcsf.reload`,
+    /// on any inode and regardless of value, triggers `reload()` instead of touching that inode --
+    /// it isn't really a per-file attribute, but reusing `setxattr` means no new control inode or
+    /// mount option is needed to recover from an expired token or stale listing. `user.gcsf.share`
+    /// and `user.gcsf.share.add` manage Drive sharing, see `setxattr_share`/`setxattr_share_add`.
+    /// Every other key is read-only Drive metadata.
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        name: &OsStr,
+        value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        let name = name.to_string_lossy();
+
+        if name == "user.gcsf.reload" {
+            match self.reload() {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("setxattr(user.gcsf.reload): {}", e);
+                    reply.error(EREMOTE);
+                }
+            }
+            return;
+        }
+
+        reject_if_readonly!(self, reply);
+
+        if name == "user.gcsf.share" {
+            return self.setxattr_share(ino, value, reply);
+        }
+
+        if name == "user.gcsf.share.add" {
+            return self.setxattr_share_add(ino, value, reply);
+        }
+
+        if name != "user.drive.starred" {
+            reply.error(ENOTSUP);
+            return;
+        }
+
+        let starred = match std::str::from_utf8(value) {
+            Ok("true") => true,
+            Ok("false") => false,
+            _ => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        match self.manager.set_starred(&FileId::Inode(ino), starred) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                error!("setxattr(ino={}, user.drive.starred): {}", ino, e);
+                reply.error(EREMOTE);
+            }
+        }
+    }
+
+    /// Handles `setxattr(2)` on `user.gcsf.share`: `value` is a JSON array of the same shape
+    /// `getxattr_share` returns, and `ino`'s sharing state is reconciled to match it exactly --
+    /// permissions present in `value` but not currently granted are created, permissions currently
+    /// granted but absent from `value` are revoked.
+    fn setxattr_share(&mut self, ino: Inode, value: &[u8], reply: ReplyEmpty) {
+        let desired: Vec<Permission> = match serde_json::from_slice(value) {
+            Ok(desired) => desired,
+            Err(e) => {
+                error!("setxattr(user.gcsf.share): invalid JSON: {}", e);
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        match self
+            .manager
+            .reconcile_permissions(&FileId::Inode(ino), &desired)
+        {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                error!("setxattr(ino={}, user.gcsf.share): {}", ino, e);
+                reply.error(EREMOTE);
+            }
+        }
+    }
+
+    /// Handles `setxattr(2)` on `user.gcsf.share.add`: grants a single permission without
+    /// affecting any other grantee. `value` is `"{role}:{grantee}"` (e.g.
+    /// `"reader:alice@example.com"`, `"writer:example.com"`, `"reader:anyone"`), with the grantee
+    /// type inferred as `user` (contains `@`), `anyone` (literal `anyone`) or `domain` (anything
+    /// else). An optional explicit `"{role}:{type}:{grantee}"` form is also accepted, for `group`
+    /// grantees or whenever the inferred type would be wrong.
+    fn setxattr_share_add(&mut self, ino: Inode, value: &[u8], reply: ReplyEmpty) {
+        let value = match std::str::from_utf8(value) {
+            Ok(value) => value,
+            Err(_) => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        let mut parts = value.splitn(3, ':');
+        let role = parts.next().filter(|s| !s.is_empty());
+        let second = parts.next().filter(|s| !s.is_empty());
+        let third = parts.next().filter(|s| !s.is_empty());
+
+        let (role, type_, grantee) = match (role, second, third) {
+            (Some(role), Some(type_), Some(grantee)) => (role, type_.to_string(), Some(grantee)),
+            (Some(role), Some("anyone"), None) => (role, "anyone".to_string(), None),
+            (Some(role), Some(grantee), None) if grantee.contains('@') => {
+                (role, "user".to_string(), Some(grantee))
+            }
+            (Some(role), Some(grantee), None) => (role, "domain".to_string(), Some(grantee)),
+            _ => {
+                error!("setxattr(user.gcsf.share.add): expected \"role:grantee\"");
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        let email = if type_ == "domain" || type_ == "anyone" {
+            None
+        } else {
+            grantee
+        };
+        let domain = if type_ == "domain" { grantee } else { None };
+
+        match self.manager.add_permission_if_not_exists(
+            &FileId::Inode(ino),
+            email,
+            domain,
+            role,
+            &type_,
+        ) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                error!("setxattr(ino={}, user.gcsf.share.add): {}", ino, e);
+                reply.error(EREMOTE);
+            }
+        }
+    }
+
+    /// No `user.drive.*` attribute can be meaningfully removed (they mirror Drive fields that
+    /// are always present in some state), so this always reports "not supported".
+    fn removexattr(&mut self, _req: &Request, _ino: Inode, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(ENOTSUP);
+    }
+
+    /// F_GETLK semantics: reports the lock that would conflict with `[start, end]`/`typ` for
+    /// `lock_owner`, or `F_UNLCK` if none would.
+    fn getlk(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        _pid: u32,
+        reply: ReplyLock,
+    ) {
+        let conflict = self
+            .locks
+            .get(&ino)
+            .and_then(|ranges| find_lock_conflict(ranges, lock_owner, start, end, typ));
+
+        match conflict {
+            Some(lock) => reply.locked(lock.start, lock.end, lock.typ, lock.pid),
+            None => reply.locked(start, end, libc::F_UNLCK, 0),
+        }
+    }
+
+    /// F_SETLK/F_SETLKW semantics. A conflicting lock always yields `EAGAIN`, even when `sleep`
+    /// (F_SETLKW) is set: this server answers each FUSE request immediately and has no way to
+    /// park the caller until the conflicting lock clears.
+    fn setlk(
+        &mut self,
+        _req: &Request,
+        ino: Inode,
+        _fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        if typ == libc::F_UNLCK {
+            if let Some(ranges) = self.locks.get_mut(&ino) {
+                release_lock_range(ranges, lock_owner, start, end);
+            }
+            reply.ok();
+            return;
+        }
+
+        let ranges = self.locks.entry(ino).or_insert_with(Vec::new);
+        match find_lock_conflict(ranges, lock_owner, start, end, typ) {
+            Some(_) => {
+                if sleep {
+                    debug!(
+                        "setlk(ino={}): blocking lock requested but not supported, returning EAGAIN",
+                        ino
+                    );
+                }
+                reply.error(EAGAIN);
+            }
+            None => {
+                acquire_lock_range(ranges, lock_owner, start, end, typ, pid);
+                reply.ok();
+            }
+        }
+    }
 }
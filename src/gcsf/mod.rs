@@ -1,11 +1,15 @@
 pub use self::config::Config;
-pub use self::drive_facade::DriveFacade;
-pub use self::file::{File, FileId};
-pub use self::file_manager::FileManager;
+pub use self::drive_facade::{DriveFacade, FlushConflict, Permission};
+pub use self::file::{set_suffix_scheme, File, FileId, SuffixScheme, SHORTCUT_MIME_TYPE};
+pub use self::file_manager::{DuplicatePolicy, ExchangeConflict, FileManager};
 
 pub mod auth;
 mod config;
+mod disk_cache;
 mod drive_facade;
+pub mod encryption;
 mod file;
 mod file_manager;
 pub mod filesystem;
+mod snapshot;
+pub mod verify;
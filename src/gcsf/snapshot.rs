@@ -0,0 +1,284 @@
+//! On-disk persistence for the `FileManager` tree.
+//!
+//! `fuser::FileAttr`/`FileType` are foreign types and don't implement `Serialize`, so this module
+//! keeps small shim structs that mirror their fields and converts to/from them at the boundary.
+//! The whole tree is flattened into a parent-first node list, bincode-encoded and zstd-compressed
+//! into a single file. `SCHEMA_VERSION` is bumped whenever this layout changes so that loading an
+//! index written by an older/newer GCSF forces a clean rebuild instead of a garbage deserialize.
+
+use super::file::File;
+use super::file_manager::{DriveId, Inode};
+use failure::{err_msg, Error};
+use fuser::{FileAttr, FileType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SystemTimeShim {
+    secs: u64,
+    nanos: u32,
+}
+
+impl From<SystemTime> for SystemTimeShim {
+    fn from(t: SystemTime) -> Self {
+        let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+        SystemTimeShim {
+            secs: d.as_secs(),
+            nanos: d.subsec_nanos(),
+        }
+    }
+}
+
+impl From<SystemTimeShim> for SystemTime {
+    fn from(t: SystemTimeShim) -> Self {
+        UNIX_EPOCH + Duration::new(t.secs, t.nanos)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum FileTypeShim {
+    NamedPipe,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    RegularFile,
+    Symlink,
+    Socket,
+}
+
+impl From<FileType> for FileTypeShim {
+    fn from(kind: FileType) -> Self {
+        match kind {
+            FileType::NamedPipe => FileTypeShim::NamedPipe,
+            FileType::CharDevice => FileTypeShim::CharDevice,
+            FileType::BlockDevice => FileTypeShim::BlockDevice,
+            FileType::Directory => FileTypeShim::Directory,
+            FileType::RegularFile => FileTypeShim::RegularFile,
+            FileType::Symlink => FileTypeShim::Symlink,
+            FileType::Socket => FileTypeShim::Socket,
+        }
+    }
+}
+
+impl From<FileTypeShim> for FileType {
+    fn from(kind: FileTypeShim) -> Self {
+        match kind {
+            FileTypeShim::NamedPipe => FileType::NamedPipe,
+            FileTypeShim::CharDevice => FileType::CharDevice,
+            FileTypeShim::BlockDevice => FileType::BlockDevice,
+            FileTypeShim::Directory => FileType::Directory,
+            FileTypeShim::RegularFile => FileType::RegularFile,
+            FileTypeShim::Symlink => FileType::Symlink,
+            FileTypeShim::Socket => FileType::Socket,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileAttrShim {
+    ino: u64,
+    size: u64,
+    blocks: u64,
+    blksize: u32,
+    atime: SystemTimeShim,
+    mtime: SystemTimeShim,
+    ctime: SystemTimeShim,
+    crtime: SystemTimeShim,
+    kind: FileTypeShim,
+    perm: u16,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u32,
+    flags: u32,
+}
+
+impl From<FileAttr> for FileAttrShim {
+    fn from(attr: FileAttr) -> Self {
+        FileAttrShim {
+            ino: attr.ino,
+            size: attr.size,
+            blocks: attr.blocks,
+            blksize: attr.blksize,
+            atime: attr.atime.into(),
+            mtime: attr.mtime.into(),
+            ctime: attr.ctime.into(),
+            crtime: attr.crtime.into(),
+            kind: attr.kind.into(),
+            perm: attr.perm,
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            rdev: attr.rdev,
+            flags: attr.flags,
+        }
+    }
+}
+
+impl From<FileAttrShim> for FileAttr {
+    fn from(attr: FileAttrShim) -> Self {
+        FileAttr {
+            ino: attr.ino,
+            size: attr.size,
+            blocks: attr.blocks,
+            blksize: attr.blksize,
+            atime: attr.atime.into(),
+            mtime: attr.mtime.into(),
+            ctime: attr.ctime.into(),
+            crtime: attr.crtime.into(),
+            kind: attr.kind.into(),
+            perm: attr.perm,
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            rdev: attr.rdev,
+            flags: attr.flags,
+        }
+    }
+}
+
+/// Serializable mirror of `File`.
+#[derive(Serialize, Deserialize)]
+struct StoredFile {
+    name: String,
+    attr: FileAttrShim,
+    identical_name_id: Option<usize>,
+    drive_file: Option<drive3::api::File>,
+    trashed_parent_id: Option<String>,
+    symlink_target: Option<String>,
+    export_mime_type: Option<String>,
+}
+
+impl From<&File> for StoredFile {
+    fn from(file: &File) -> Self {
+        StoredFile {
+            name: file.name.clone(),
+            attr: file.attr.into(),
+            identical_name_id: file.identical_name_id,
+            drive_file: file.drive_file.clone(),
+            trashed_parent_id: file.trashed_parent_id.clone(),
+            symlink_target: file.symlink_target.clone(),
+            export_mime_type: file.export_mime_type.clone(),
+        }
+    }
+}
+
+impl From<StoredFile> for File {
+    fn from(stored: StoredFile) -> Self {
+        File {
+            name: stored.name,
+            attr: stored.attr.into(),
+            identical_name_id: stored.identical_name_id,
+            drive_file: stored.drive_file,
+            trashed_parent_id: stored.trashed_parent_id,
+            symlink_target: stored.symlink_target,
+            export_mime_type: stored.export_mime_type,
+        }
+    }
+}
+
+/// A single tree entry, stored in parent-first (pre-order) order so that reloading can always
+/// insert a node's parent before the node itself.
+#[derive(Serialize, Deserialize)]
+pub struct StoredNode {
+    pub inode: Inode,
+    pub parent: Option<Inode>,
+    file: StoredFile,
+}
+
+/// The full on-disk representation of a `FileManager`.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    schema_version: u32,
+    pub nodes: Vec<StoredNode>,
+    pub drive_ids: HashMap<DriveId, Inode>,
+    pub last_inode: Inode,
+    last_sync: SystemTimeShim,
+    /// The last `changes.list` page token seen by the `DriveFacade` (see
+    /// `DriveFacade::persisted_changes_token`), so a reload resumes incrementally instead of
+    /// restarting from `get_start_page_token()`.
+    pub changes_token: Option<String>,
+}
+
+impl Snapshot {
+    pub fn new(
+        nodes: Vec<StoredNode>,
+        drive_ids: HashMap<DriveId, Inode>,
+        last_inode: Inode,
+        last_sync: SystemTime,
+        changes_token: Option<String>,
+    ) -> Self {
+        Snapshot {
+            schema_version: SCHEMA_VERSION,
+            nodes,
+            drive_ids,
+            last_inode,
+            last_sync: last_sync.into(),
+            changes_token,
+        }
+    }
+
+    pub fn last_sync(&self) -> SystemTime {
+        // Clone the shim fields out manually since `SystemTimeShim` doesn't implement `Copy`.
+        SystemTimeShim {
+            secs: self.last_sync.secs,
+            nanos: self.last_sync.nanos,
+        }
+        .into()
+    }
+}
+
+/// Builds a single `StoredNode` entry for `inode`, recording its parent (if any).
+pub fn make_node(inode: Inode, parent: Option<Inode>, file: &File) -> StoredNode {
+    StoredNode {
+        inode,
+        parent,
+        file: StoredFile::from(file),
+    }
+}
+
+/// Recovers the original `File` from a previously-stored node.
+pub fn file_from_stored(node: StoredNode) -> File {
+    node.file.into()
+}
+
+/// Compresses and writes `snapshot` to `path`.
+pub fn save(path: &Path, snapshot: &Snapshot) -> Result<(), Error> {
+    let encoded = bincode::serialize(snapshot)
+        .map_err(|e| err_msg(format!("snapshot: could not encode tree: {}", e)))?;
+    let compressed = zstd::stream::encode_all(&encoded[..], 0)
+        .map_err(|e| err_msg(format!("snapshot: could not compress tree: {}", e)))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| err_msg(format!("snapshot: could not create {:?}: {}", parent, e)))?;
+    }
+
+    fs::write(path, compressed)
+        .map_err(|e| err_msg(format!("snapshot: could not write {:?}: {}", path, e)))
+}
+
+/// Reads and decompresses a snapshot from `path`. Fails (forcing a cold rebuild upstream) if the
+/// file is missing, corrupt, or was written by an incompatible schema version.
+pub fn load(path: &Path) -> Result<Snapshot, Error> {
+    let compressed =
+        fs::read(path).map_err(|e| err_msg(format!("snapshot: could not read {:?}: {}", path, e)))?;
+    let encoded = zstd::stream::decode_all(&compressed[..])
+        .map_err(|e| err_msg(format!("snapshot: could not decompress {:?}: {}", path, e)))?;
+    let snapshot: Snapshot = bincode::deserialize(&encoded)
+        .map_err(|e| err_msg(format!("snapshot: could not decode {:?}: {}", path, e)))?;
+
+    if snapshot.schema_version != SCHEMA_VERSION {
+        return Err(err_msg(format!(
+            "snapshot: {:?} was written with schema version {} but this build expects {}",
+            path, snapshot.schema_version, SCHEMA_VERSION
+        )));
+    }
+
+    Ok(snapshot)
+}
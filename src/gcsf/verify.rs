@@ -0,0 +1,142 @@
+//! Checks local/Drive consistency: walks the file tree, reads each file's full content through
+//! the same `DriveFacade::read` path a mount would use, and compares it against what Drive
+//! reports for that file (its `md5Checksum` and size). Powers the `gcsf verify` CLI command (see
+//! `main.rs`), a rclone-style "check" mode that lets a user confirm a sync completed correctly
+//! before trusting a mount.
+
+use super::file::File;
+use super::file_manager::FileManager;
+use fuser::FileType;
+use std::fmt;
+
+/// How much of a file is requested from `DriveFacade::read` at a time while reconstructing its
+/// content for hashing. Large enough to keep round-trips infrequent, small enough that verifying
+/// one huge file doesn't require an unbounded single allocation from `DriveFacade`'s own cache.
+const READ_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// One discrepancy found while verifying a file against Drive.
+pub enum Mismatch {
+    /// Drive reports no `md5Checksum` for this file (e.g. a Google-native document), so there's
+    /// nothing to compare its content against.
+    NoChecksum,
+    /// The file's content could not be read back at all.
+    ReadFailed,
+    /// The locally reconstructed content is a different size than Drive's reported `size`.
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The locally reconstructed content hashes differently than Drive's reported `md5Checksum`.
+    ChecksumMismatch { expected: String, actual: String },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::NoChecksum => write!(f, "no md5Checksum reported by Drive"),
+            Mismatch::ReadFailed => write!(f, "could not read file content"),
+            Mismatch::SizeMismatch { expected, actual } => {
+                write!(f, "size mismatch: Drive reports {} bytes, read {}", expected, actual)
+            }
+            Mismatch::ChecksumMismatch { expected, actual } => {
+                write!(f, "md5 mismatch: Drive reports {}, computed {}", expected, actual)
+            }
+        }
+    }
+}
+
+/// Result of a full `verify` pass. Files that checked out cleanly are simply absent from
+/// `mismatches`, so an empty list means everything matched.
+pub struct Report {
+    pub files_checked: usize,
+    pub mismatches: Vec<(String, Mismatch)>,
+}
+
+/// Reconstructs `file`'s full content by repeatedly calling `DriveFacade::read`, the same way a
+/// FUSE `read()` would, `READ_BLOCK_SIZE` bytes at a time. Returns `None` if any block fails.
+fn read_all(manager: &mut FileManager, file: &File) -> Option<Vec<u8>> {
+    let drive_id = file.drive_id()?;
+    let mime_type = file.mime_type();
+    let export_mime_type = file.export_mime_type.clone();
+
+    let mut data = Vec::with_capacity(file.attr.size as usize);
+    let mut offset = 0usize;
+    loop {
+        let chunk = manager.df.read(
+            &drive_id,
+            mime_type.clone(),
+            export_mime_type.clone(),
+            offset,
+            READ_BLOCK_SIZE,
+        )?;
+
+        if chunk.is_empty() {
+            break;
+        }
+
+        data.extend_from_slice(chunk);
+        offset += chunk.len();
+        if chunk.len() < READ_BLOCK_SIZE {
+            break;
+        }
+    }
+
+    Some(data)
+}
+
+/// Walks every non-trashed, non-directory file in `manager`'s tree and checks its content against
+/// Drive (see module docs).
+pub fn verify(manager: &mut FileManager) -> Report {
+    let files: Vec<File> = manager
+        .files
+        .values()
+        .filter(|file| !file.is_trashed() && file.kind() != FileType::Directory)
+        .cloned()
+        .collect();
+
+    let files_checked = files.len();
+    let mut mismatches = Vec::new();
+
+    for file in &files {
+        let path = manager.full_path(file);
+
+        let expected_md5 = match file.md5_checksum() {
+            Some(md5) => md5,
+            None => {
+                mismatches.push((path, Mismatch::NoChecksum));
+                continue;
+            }
+        };
+
+        let data = match read_all(manager, file) {
+            Some(data) => data,
+            None => {
+                mismatches.push((path, Mismatch::ReadFailed));
+                continue;
+            }
+        };
+
+        if data.len() as u64 != file.attr.size {
+            mismatches.push((
+                path.clone(),
+                Mismatch::SizeMismatch {
+                    expected: file.attr.size,
+                    actual: data.len() as u64,
+                },
+            ));
+        }
+
+        let actual_md5 = format!("{:x}", md5::compute(&data));
+        if actual_md5 != expected_md5 {
+            mismatches.push((
+                path,
+                Mismatch::ChecksumMismatch {
+                    expected: expected_md5,
+                    actual: actual_md5,
+                },
+            ));
+        }
+    }
+
+    Report {
+        files_checked,
+        mismatches,
+    }
+}
@@ -108,18 +108,30 @@
     warnings,
     while_true
 )]
+extern crate aes_gcm;
+extern crate base64;
+extern crate bincode;
 extern crate failure;
 extern crate fuser;
 extern crate google_drive3 as drive3;
 extern crate id_tree;
+extern crate keyring;
 extern crate libc;
+extern crate md5;
+extern crate memmap;
+extern crate mime_guess;
 extern crate mime_sniffer;
+extern crate rand;
+extern crate sha2;
+extern crate users;
+extern crate webbrowser;
 #[macro_use]
 extern crate log;
 #[macro_use]
 extern crate maplit;
 extern crate lru_time_cache;
 extern crate pretty_env_logger;
+extern crate regex;
 extern crate serde;
 extern crate serde_json;
 #[macro_use]
@@ -127,11 +139,14 @@ extern crate serde_derive;
 extern crate time;
 #[macro_use]
 extern crate lazy_static;
+extern crate zstd;
 
 mod gcsf;
 
 pub use crate::gcsf::filesystem::{Gcsf, NullFs};
 pub use crate::gcsf::{Config, DriveFacade, FileManager};
+pub use crate::gcsf::auth;
+pub use crate::gcsf::verify;
 
 #[cfg(test)]
 mod tests;
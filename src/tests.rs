@@ -34,6 +34,9 @@ mod rename_identical_files_tests {
             },
             identical_name_id: None,
             drive_file: Some(drive_file),
+            trashed_parent_id: None,
+            symlink_target: None,
+            export_mime_type: None,
         }
     }
 
@@ -69,6 +72,9 @@ mod rename_identical_files_tests {
             },
             identical_name_id: None,
             drive_file: Some(drive_file),
+            trashed_parent_id: None,
+            symlink_target: None,
+            export_mime_type: None,
         }
     }
 
@@ -336,3 +342,424 @@ mod rename_identical_files_tests {
         std::mem::forget(fm);
     }
 }
+
+#[cfg(test)]
+mod lock_range_tests {
+    use crate::gcsf::filesystem::{
+        acquire_lock_range_for_testing, find_lock_conflict_for_testing, release_lock_range_for_testing,
+    };
+    use libc::{F_RDLCK, F_WRLCK};
+
+    #[test]
+    fn non_overlapping_write_locks_from_different_owners_do_not_conflict() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+
+        assert!(!find_lock_conflict_for_testing(&ranges, 2, 10, 19, F_WRLCK));
+    }
+
+    #[test]
+    fn overlapping_write_locks_from_different_owners_conflict() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+
+        assert!(find_lock_conflict_for_testing(&ranges, 2, 5, 14, F_WRLCK));
+    }
+
+    #[test]
+    fn overlapping_read_locks_from_different_owners_do_not_conflict() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_RDLCK, 100);
+
+        assert!(!find_lock_conflict_for_testing(&ranges, 2, 5, 14, F_RDLCK));
+    }
+
+    #[test]
+    fn read_lock_conflicts_with_an_overlapping_write_lock() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+
+        assert!(find_lock_conflict_for_testing(&ranges, 2, 5, 14, F_RDLCK));
+    }
+
+    #[test]
+    fn same_owner_overlapping_ranges_never_conflict_with_themselves() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+
+        assert!(!find_lock_conflict_for_testing(&ranges, 1, 5, 14, F_WRLCK));
+    }
+
+    #[test]
+    fn acquiring_touching_same_type_ranges_merges_them_into_one() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+        acquire_lock_range_for_testing(&mut ranges, 1, 10, 19, F_WRLCK, 100);
+
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].0, ranges[0].1), (0, 19));
+    }
+
+    #[test]
+    fn upgrading_a_read_lock_to_a_write_lock_drops_the_covered_read_range() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_RDLCK, 100);
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].2, F_WRLCK);
+    }
+
+    /// Regression test for the F_UNLCK-drops-everything bug: unlocking one of an owner's two
+    /// separate ranges must leave the other one (on the same inode) intact.
+    #[test]
+    fn unlocking_one_range_leaves_the_owners_other_range_untouched() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+        acquire_lock_range_for_testing(&mut ranges, 1, 100, 199, F_WRLCK, 100);
+
+        release_lock_range_for_testing(&mut ranges, 1, 0, 9);
+
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].0, ranges[0].1), (100, 199));
+        assert!(!find_lock_conflict_for_testing(&ranges, 2, 0, 9, F_WRLCK));
+        assert!(find_lock_conflict_for_testing(&ranges, 2, 100, 199, F_WRLCK));
+    }
+
+    #[test]
+    fn unlocking_a_sub_range_in_the_middle_splits_the_lock_in_two() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 99, F_WRLCK, 100);
+
+        release_lock_range_for_testing(&mut ranges, 1, 40, 59);
+
+        assert_eq!(ranges.len(), 2);
+        let mut bounds: Vec<(u64, u64)> = ranges.iter().map(|r| (r.0, r.1)).collect();
+        bounds.sort();
+        assert_eq!(bounds, vec![(0, 39), (60, 99)]);
+        // The unlocked middle no longer conflicts with anyone.
+        assert!(!find_lock_conflict_for_testing(&ranges, 2, 40, 59, F_WRLCK));
+    }
+
+    #[test]
+    fn unlocking_a_range_belonging_to_a_different_owner_is_a_no_op() {
+        let mut ranges = Vec::new();
+        acquire_lock_range_for_testing(&mut ranges, 1, 0, 9, F_WRLCK, 100);
+
+        release_lock_range_for_testing(&mut ranges, 2, 0, 9);
+
+        assert_eq!(ranges.len(), 1);
+        assert!(find_lock_conflict_for_testing(&ranges, 2, 0, 9, F_WRLCK));
+    }
+}
+
+#[cfg(test)]
+mod duplicate_policy_tests {
+    use crate::gcsf::{DuplicatePolicy, FileManager};
+
+    #[test]
+    fn keep_newest_trashes_everyone_but_the_last() {
+        // `live` is oldest-first by mtime, so the last entry is the newest.
+        let live = vec![101, 102, 103];
+        let to_trash = FileManager::duplicates_to_trash_for_testing(DuplicatePolicy::KeepNewest, &live);
+        assert_eq!(to_trash, vec![101, 102]);
+    }
+
+    #[test]
+    fn keep_oldest_trashes_everyone_but_the_first() {
+        let live = vec![101, 102, 103];
+        let to_trash = FileManager::duplicates_to_trash_for_testing(DuplicatePolicy::KeepOldest, &live);
+        assert_eq!(to_trash, vec![102, 103]);
+    }
+
+    #[test]
+    fn none_trashes_nobody() {
+        let live = vec![101, 102, 103];
+        let to_trash = FileManager::duplicates_to_trash_for_testing(DuplicatePolicy::None, &live);
+        assert!(to_trash.is_empty());
+    }
+
+    #[test]
+    fn keep_newest_with_two_members() {
+        let live = vec![101, 102];
+        let to_trash = FileManager::duplicates_to_trash_for_testing(DuplicatePolicy::KeepNewest, &live);
+        assert_eq!(to_trash, vec![101]);
+    }
+}
+
+#[cfg(test)]
+mod permission_tests {
+    use crate::gcsf::filesystem::check_access_for_testing;
+    use crate::gcsf::File;
+    use fuser::{FileAttr, FileType};
+    use libc::{R_OK, W_OK, X_OK};
+    use std::time::SystemTime;
+
+    fn file_with_perm(uid: u32, gid: u32, perm: u16) -> File {
+        File {
+            name: "file".to_string(),
+            attr: FileAttr {
+                ino: 100,
+                size: 0,
+                blocks: 0,
+                blksize: 512,
+                atime: SystemTime::UNIX_EPOCH,
+                mtime: SystemTime::UNIX_EPOCH,
+                ctime: SystemTime::UNIX_EPOCH,
+                crtime: SystemTime::UNIX_EPOCH,
+                kind: FileType::RegularFile,
+                perm,
+                nlink: 1,
+                uid,
+                gid,
+                rdev: 0,
+                flags: 0,
+            },
+            identical_name_id: None,
+            drive_file: None,
+            trashed_parent_id: None,
+            symlink_target: None,
+            export_mime_type: None,
+        }
+    }
+
+    #[test]
+    fn owner_gets_owner_bits() {
+        // rwx------ : owner can do everything, nobody else can read.
+        let file = file_with_perm(1000, 1000, 0o700);
+        assert!(check_access_for_testing(&file, 1000, 1000, R_OK | W_OK | X_OK));
+    }
+
+    #[test]
+    fn non_owner_non_group_gets_other_bits() {
+        // rw-------  : nobody but the owner can read/write.
+        let file = file_with_perm(1000, 1000, 0o600);
+        // A uid/gid pair that (almost certainly) doesn't exist on the test host, so
+        // `users::get_user_groups` can't accidentally put it in the owning group.
+        assert!(!check_access_for_testing(&file, 50000, 50000, R_OK));
+    }
+
+    #[test]
+    fn group_member_gets_group_bits_even_when_not_owner() {
+        // rw-r----- : group can read but not write.
+        let file = file_with_perm(1000, 2000, 0o640);
+        assert!(check_access_for_testing(&file, 1000, 2000, W_OK));
+        // A different uid sharing the same gid still only gets group bits.
+        assert!(check_access_for_testing(&file, 3000, 2000, R_OK));
+        assert!(!check_access_for_testing(&file, 3000, 2000, W_OK));
+    }
+
+    #[test]
+    fn root_is_always_allowed_except_execute_without_any_x_bit() {
+        let file = file_with_perm(1000, 1000, 0o600);
+        assert!(check_access_for_testing(&file, 0, 0, R_OK | W_OK));
+        assert!(!check_access_for_testing(&file, 0, 0, X_OK));
+
+        let executable = file_with_perm(1000, 1000, 0o700);
+        assert!(check_access_for_testing(&executable, 0, 0, X_OK));
+    }
+}
+
+#[cfg(test)]
+mod oauth_state_tests {
+    use crate::gcsf::auth::extract_code_from_url_for_testing;
+
+    #[test]
+    fn extracts_code_and_state_together() {
+        let (code, state) =
+            extract_code_from_url_for_testing("http://localhost:8080/?code=abc123&state=xyz789")
+                .unwrap();
+        assert_eq!(code, "abc123");
+        assert_eq!(state.as_deref(), Some("xyz789"));
+    }
+
+    #[test]
+    fn state_is_none_when_absent() {
+        let (code, state) =
+            extract_code_from_url_for_testing("http://localhost:8080/?code=abc123").unwrap();
+        assert_eq!(code, "abc123");
+        assert_eq!(state, None);
+    }
+
+    #[test]
+    fn returns_none_without_a_code() {
+        assert!(extract_code_from_url_for_testing("http://localhost:8080/?state=xyz789").is_none());
+    }
+
+    #[test]
+    fn mismatched_state_does_not_match_expected() {
+        let expected_state = "the-real-state-we-generated";
+        let (_, state) = extract_code_from_url_for_testing(
+            "http://localhost:8080/?code=stolen-code&state=attacker-guess",
+        )
+        .unwrap();
+        assert_ne!(state.as_deref(), Some(expected_state));
+    }
+
+    #[test]
+    fn matching_state_does_match_expected() {
+        let expected_state = "the-real-state-we-generated";
+        let (_, state) = extract_code_from_url_for_testing(&format!(
+            "http://localhost:8080/?code=real-code&state={}",
+            expected_state
+        ))
+        .unwrap();
+        assert_eq!(state.as_deref(), Some(expected_state));
+    }
+}
+
+#[cfg(test)]
+mod encryption_nonce_tests {
+    use crate::gcsf::encryption::{block_nonce_for_testing, BASE_NONCE_LEN};
+
+    #[test]
+    fn same_base_and_index_derive_the_same_nonce() {
+        let base_nonce = [7u8; BASE_NONCE_LEN];
+        assert_eq!(
+            block_nonce_for_testing(&base_nonce, 0),
+            block_nonce_for_testing(&base_nonce, 0)
+        );
+    }
+
+    #[test]
+    fn different_block_indices_derive_different_nonces() {
+        let base_nonce = [7u8; BASE_NONCE_LEN];
+        assert_ne!(
+            block_nonce_for_testing(&base_nonce, 0),
+            block_nonce_for_testing(&base_nonce, 1)
+        );
+    }
+
+    #[test]
+    fn nonce_embeds_the_base_nonce_and_big_endian_block_index() {
+        let base_nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        let nonce = block_nonce_for_testing(&base_nonce, 42);
+
+        assert_eq!(&nonce[..BASE_NONCE_LEN], &base_nonce[..]);
+        assert_eq!(&nonce[BASE_NONCE_LEN..], &42u32.to_be_bytes()[..]);
+    }
+
+    #[test]
+    fn different_base_nonces_derive_different_nonces_at_the_same_index() {
+        let a = block_nonce_for_testing(&[1u8; BASE_NONCE_LEN], 5);
+        let b = block_nonce_for_testing(&[2u8; BASE_NONCE_LEN], 5);
+        assert_ne!(a, b);
+    }
+}
+
+#[cfg(test)]
+mod exchange_tests {
+    use crate::gcsf::{ExchangeConflict, File, FileId, FileManager};
+    use failure::err_msg;
+
+    fn create_test_file(name: &str, inode: u64, drive_id: &str) -> File {
+        use fuser::{FileAttr, FileType};
+        use std::time::SystemTime;
+
+        let drive_file = drive3::api::File {
+            id: Some(drive_id.to_string()),
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+
+        File {
+            name: name.to_string(),
+            attr: FileAttr {
+                ino: inode,
+                size: 0,
+                blocks: 0,
+                blksize: 512,
+                atime: SystemTime::UNIX_EPOCH,
+                mtime: SystemTime::UNIX_EPOCH,
+                ctime: SystemTime::UNIX_EPOCH,
+                crtime: SystemTime::UNIX_EPOCH,
+                kind: FileType::RegularFile,
+                perm: 0o644,
+                nlink: 1,
+                uid: 1000,
+                gid: 1000,
+                rdev: 0,
+                flags: 0,
+            },
+            identical_name_id: None,
+            drive_file: Some(drive_file),
+            trashed_parent_id: None,
+            symlink_target: None,
+            export_mime_type: None,
+        }
+    }
+
+    /// Regression test for the exchange-collision bug: `FileManager::exchange`'s own local
+    /// restaging (exercised here directly through `exchange_locally`, not a hand-replayed copy of
+    /// it) must land both files on their exact swapped names, with neither (parent, name) slot
+    /// ever colliding.
+    #[test]
+    fn restaging_through_a_scratch_name_swaps_both_files_cleanly() {
+        let mut fm = FileManager::new_for_testing(true);
+
+        let file_a = create_test_file("photo1.jpg", 101, "drive_a");
+        let file_b = create_test_file("photo2.jpg", 102, "drive_b");
+        fm.add_test_file(file_a, 1).unwrap(); // ROOT_INODE = 1
+        fm.add_test_file(file_b, 1).unwrap();
+
+        let (a_inode, b_inode, a_parent, b_parent, a_name, b_name) = fm
+            .exchange_locally_for_testing(&FileId::Inode(101), &FileId::Inode(102))
+            .unwrap();
+
+        assert_eq!((a_inode, b_inode), (101, 102));
+        assert_eq!((a_parent, b_parent), (1, 1));
+        assert_eq!((a_name.as_str(), b_name.as_str()), ("photo1.jpg", "photo2.jpg"));
+
+        assert_eq!(fm.get_file(&FileId::Inode(101)).unwrap().name(), "photo2.jpg");
+        assert_eq!(fm.get_file(&FileId::Inode(102)).unwrap().name(), "photo1.jpg");
+
+        // Each final name resolves to exactly the file that now holds it -- no leftover
+        // same-(parent, name) collision and no stray disambiguating suffix.
+        assert_eq!(
+            fm.get_inode(&FileId::ParentAndName {
+                parent: 1,
+                name: "photo1.jpg".to_string()
+            }),
+            Some(102)
+        );
+        assert_eq!(
+            fm.get_inode(&FileId::ParentAndName {
+                parent: 1,
+                name: "photo2.jpg".to_string()
+            }),
+            Some(101)
+        );
+        assert_eq!(fm.get_file(&FileId::Inode(101)).unwrap().identical_name_id, None);
+        assert_eq!(fm.get_file(&FileId::Inode(102)).unwrap().identical_name_id, None);
+
+        // Prevent drop to avoid undefined behavior with uninitialized DriveFacade
+        std::mem::forget(fm);
+    }
+
+    /// `exchange` reports a partial Drive-side failure as a `downcast_ref`-able `ExchangeConflict`
+    /// rather than a generic error, and its `Display` message reflects whether the rollback it
+    /// attempted actually put the local tree and Drive back in sync.
+    #[test]
+    fn exchange_conflict_is_downcastable_and_reports_whether_it_resynced() {
+        let resynced: failure::Error = ExchangeConflict {
+            a: "drive_a".to_string(),
+            b: "drive_b".to_string(),
+            resynced: true,
+            cause: err_msg("network error"),
+        }
+        .into();
+
+        assert!(resynced.downcast_ref::<ExchangeConflict>().is_some());
+        assert!(resynced.to_string().contains("put back in sync"));
+
+        let unresynced: failure::Error = ExchangeConflict {
+            a: "drive_a".to_string(),
+            b: "drive_b".to_string(),
+            resynced: false,
+            cause: err_msg("network error"),
+        }
+        .into();
+
+        assert!(unresynced.to_string().contains("out of sync"));
+    }
+}